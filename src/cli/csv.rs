@@ -1,60 +1,1206 @@
-use std::{fmt::Display, str::FromStr};
+use std::{fmt::Display, path::PathBuf, str::FromStr};
 
-use crate::{process_csv, CmdExector};
-use clap::Args;
+use crate::{
+    input_encoding, process_csv, process_csv_decrypt, process_csv_dedup, process_csv_diff,
+    process_csv_encrypt, process_csv_fake, process_csv_from, process_csv_head, process_csv_join,
+    process_csv_mask, process_csv_merge, process_csv_query, process_csv_sample,
+    process_csv_schema_infer, process_csv_sort, process_csv_split, process_csv_stats,
+    process_csv_tail, process_csv_validate, split_compression, CmdExector, ColumnType, Expr,
+    QuerySource, SplitBy,
+};
+use chrono::NaiveDate;
+use clap::{ArgAction, ArgGroup, Args, Subcommand};
+use encoding_rs::Encoding;
+use enum_dispatch::enum_dispatch;
 
-use super::verify_file;
+use super::{verify_file, verify_path};
 
 #[derive(Debug, Args)]
-#[command(name = "csv", about = "csv subcommand")]
+#[command(
+    name = "csv",
+    about = "csv subcommand",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 pub struct CsvOpts {
-    /// Input CSV file
-    #[arg(short, long, value_name = "input", value_parser = verify_file)]
-    pub input: String,
+    #[command(subcommand)]
+    pub cmd: Option<CsvSubCmd>,
+
+    /// Convert CSV to json/yaml/toml when no subcommand is given
+    #[command(flatten)]
+    pub convert: CsvConvertOpts,
+}
+
+#[derive(Debug, Subcommand)]
+#[enum_dispatch(CmdExector)]
+pub enum CsvSubCmd {
+    #[command(about = "Convert an array of objects in json/yaml/toml to CSV")]
+    From(CsvFromOpts),
+    #[command(alias = "describe", about = "Profile the columns of a CSV file")]
+    Stats(CsvStatsOpts),
+    #[command(about = "Sort a CSV file by one or more columns")]
+    Sort(CsvSortOpts),
+    #[command(about = "Remove duplicate records from a CSV file")]
+    Dedup(CsvDedupOpts),
+    #[command(about = "Join two CSV files on a key column")]
+    Join(CsvJoinOpts),
+    #[command(about = "Query CSV files with SQL")]
+    Query(CsvQueryOpts),
+    #[command(about = "Show the rows added, removed and modified between two CSV files")]
+    Diff(CsvDiffOpts),
+    #[command(about = "Split a CSV file into chunk files by row count or column value")]
+    Split(CsvSplitOpts),
+    #[command(about = "Concatenate CSV files, matching columns by name")]
+    Merge(CsvMergeOpts),
+    #[command(about = "Show the first records of a CSV file")]
+    Head(CsvHeadOpts),
+    #[command(about = "Show the last records of a CSV file")]
+    Tail(CsvTailOpts),
+    #[command(about = "Pick random records from a CSV file")]
+    Sample(CsvSampleOpts),
+    #[command(about = "Mask personal data in CSV columns")]
+    Mask(CsvMaskOpts),
+    #[command(about = "Encrypt the cells of CSV columns")]
+    Encrypt(CsvEncryptOpts),
+    #[command(about = "Decrypt the cells of CSV columns")]
+    Decrypt(CsvDecryptOpts),
+    #[command(about = "Generate fake records from a CSV schema")]
+    Fake(CsvFakeOpts),
+    #[command(about = "Check a CSV file against a schema and report every violation")]
+    Validate(CsvValidateOpts),
+    #[command(subcommand, about = "Work with CSV schemas")]
+    Schema(CsvSchemaSubCmd),
+}
+
+#[derive(Debug, Subcommand)]
+#[enum_dispatch(CmdExector)]
+pub enum CsvSchemaSubCmd {
+    #[command(about = "Infer a schema from a CSV file")]
+    Infer(CsvSchemaInferOpts),
+}
 
-    /// Output JSON file
+#[derive(Debug, Args)]
+pub struct CsvConvertOpts {
+    /// Input CSV file, only optional when a subcommand is given
+    #[arg(short, long, value_name = "input", value_parser = verify_file, required = true)]
+    pub input: Option<String>,
+
+    /// Output file, "-" for stdout
     #[arg(short, long, value_name = "output", value_parser = verify_output_format)]
     pub output: Option<String>,
 
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    #[command(flatten)]
+    pub types: CsvTypeOpts,
+
+    #[command(flatten)]
+    pub filter: CsvFilterOpts,
+
+    /// format for output
+    #[arg(long, default_value = "json", value_parser = OutputFormat::from_str)]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub writer: CsvWriterOpts,
+}
+
+/// Settings used to parse the CSV input
+#[derive(Debug, Clone, Args)]
+pub struct CsvReaderOpts {
     /// Delimiter
-    #[arg(short, long, value_name = "delimiter", default_value_t = ',')]
-    pub delimiter: char,
+    #[arg(short, long, value_name = "delimiter", default_value = ",", value_parser = parse_csv_char)]
+    pub delimiter: u8,
+
+    /// Quote character
+    #[arg(long, default_value = "\"", value_parser = parse_csv_char)]
+    pub quote: u8,
+
+    /// Escape character for quotes, doubled quotes are used if not set
+    #[arg(long, value_parser = parse_csv_char)]
+    pub escape: Option<u8>,
+
+    /// Lines starting with this character are skipped
+    #[arg(long, value_parser = parse_csv_char)]
+    pub comment: Option<u8>,
+
+    /// Allow records with a different number of fields
+    #[arg(long)]
+    pub flexible: bool,
 
     /// Has header or not
-    #[arg(long, default_value_t = true)]
+    #[arg(long, default_value_t = true, action = ArgAction::Set, num_args = 0..=1, default_missing_value = "true")]
     pub header: bool,
 
+    /// Column names, comma separated. Replaces the header row if there is one
+    #[arg(long, value_delimiter = ',')]
+    pub columns: Option<Vec<String>>,
+
+    /// Encoding of the input, e.g. utf-8, utf-16le, latin1 or windows-1252. auto
+    /// looks at the first 64 KiB: BOMs, UTF-16, else windows-1252 for invalid UTF-8.
+    /// It reports the encoding it picked and fails on bytes that encoding cannot decode
+    #[arg(long, default_value = "auto", value_parser = CsvEncoding::from_str)]
+    pub encoding: CsvEncoding,
+}
+
+impl Default for CsvReaderOpts {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+            escape: None,
+            comment: None,
+            flexible: false,
+            header: true,
+            columns: None,
+            encoding: CsvEncoding::Auto,
+        }
+    }
+}
+
+/// Settings used to convert CSV fields into typed values
+#[derive(Debug, Clone, Default, Args)]
+pub struct CsvTypeOpts {
+    /// Detect integers, floats, booleans and nulls instead of keeping strings
+    #[arg(long)]
+    pub infer_types: bool,
+
+    /// Also detect dates when inferring types
+    #[arg(long)]
+    pub infer_dates: bool,
+
+    /// Extra chrono format for dates, e.g. "%d.%m.%Y"
+    #[arg(long = "date-format", value_name = "FORMAT")]
+    pub date_formats: Vec<String>,
+
+    /// Type of a column, e.g. "Kit Number=int"
+    #[arg(long = "type", value_name = "COLUMN=TYPE")]
+    pub types: Vec<ColumnTypeOverride>,
+
+    /// Schema file (yaml/toml/json) to enforce, columns get the declared types and
+    /// the conversion stops at the first violation
+    #[arg(long, value_parser = verify_file)]
+    pub schema: Option<String>,
+}
+
+/// Settings used to pick the records and columns to output
+#[derive(Debug, Clone, Default, Args)]
+pub struct CsvFilterOpts {
+    /// Columns to output in this order, comma separated
+    #[arg(long, value_delimiter = ',')]
+    pub select: Option<Vec<String>>,
+
+    /// Rename a column, e.g. "Kit Number=kit"
+    #[arg(long, value_name = "OLD=NEW")]
+    pub rename: Vec<ColumnRename>,
+
+    /// Only output records matching the expression, e.g.
+    /// "Position == 'Goalkeeper' && `Kit Number` > 10". Supports == != < <= > >=,
+    /// =~ !~ for regex, && || ! and parentheses
+    #[arg(long = "where", value_name = "EXPR", value_parser = Expr::from_str)]
+    pub filter: Option<Expr>,
+}
+
+/// Settings used to write the converted records
+#[derive(Debug, Clone, Args)]
+pub struct CsvWriterOpts {
+    /// Key of the array of tables holding the records in TOML output
+    #[arg(long, default_value = "rows")]
+    pub toml_key: String,
+
+    /// Write records as they are read with bounded memory, JSON is written as
+    /// one record per line and types are inferred from the first rows only.
+    /// Always on for ndjson
+    #[arg(long)]
+    pub stream: bool,
+
+    /// Column alignment in md/html/txt tables, auto aligns numbers to the right
+    #[arg(long, default_value = "auto", value_parser = Align::from_str)]
+    pub align: Align,
+
+    /// Truncate cells longer than this in md/html/txt tables
+    #[arg(long)]
+    pub max_width: Option<usize>,
+
+    /// Delimiter of CSV output
+    #[arg(long, default_value = ",", value_parser = parse_csv_char)]
+    pub out_delimiter: u8,
+
+    /// Rebuild nested objects and arrays from column names like "address.city"
+    /// and "tags[0]"
+    #[arg(long)]
+    pub unflatten: bool,
+}
+
+impl Default for CsvWriterOpts {
+    fn default() -> Self {
+        Self {
+            toml_key: "rows".to_string(),
+            stream: false,
+            align: Align::Auto,
+            max_width: None,
+            out_delimiter: b',',
+            unflatten: false,
+        }
+    }
+}
+
+/// New name of a column
+#[derive(Debug, Clone)]
+pub struct ColumnRename {
+    pub from: String,
+    pub to: String,
+}
+
+/// Column to sort by with its order, parsed from "COLUMN[:num][:desc]"
+#[derive(Debug, Clone)]
+pub struct SortKey {
+    pub column: String,
+    pub numeric: bool,
+    pub desc: bool,
+}
+
+/// Key columns of a join, the same name on both sides unless given as "LEFT=RIGHT"
+#[derive(Debug, Clone)]
+pub struct JoinOn {
+    pub left: String,
+    pub right: String,
+}
+
+/// A CSV file queried as a table, "NAME=PATH" or a path named after its file stem
+#[derive(Debug, Clone)]
+pub struct QueryTable {
+    pub name: Option<String>,
+    pub path: String,
+}
+
+/// Type override of a single column
+#[derive(Debug, Clone)]
+pub struct ColumnTypeOverride {
+    pub name: String,
+    pub column_type: ColumnType,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvFromOpts {
+    /// Input json/yaml/toml file
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// Output CSV file, "-" for stdout
+    #[arg(short, long, default_value = "output.csv")]
+    pub output: String,
+
+    /// Format of the input, detected from the file extension if not set
+    #[arg(long, value_parser = OutputFormat::from_str)]
+    pub format: Option<OutputFormat>,
+
+    /// Key holding the array of records when the root is an object
+    #[arg(long)]
+    pub key: Option<String>,
+
+    /// Delimiter
+    #[arg(short, long, default_value = ",", value_parser = parse_csv_char)]
+    pub delimiter: u8,
+
+    /// Flatten nested objects and arrays into columns like "address.city" and "tags[0]"
+    #[arg(long)]
+    pub flatten: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvStatsOpts {
+    /// Input CSV file
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// Output file, "-" for stdout
+    #[arg(short, long, default_value = "-", value_parser = verify_output_format)]
+    pub output: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
     /// format for output
-    #[arg(long, default_value = "json", value_parser = OutputFormat::from_str)]
+    #[arg(long, default_value = "txt", value_parser = OutputFormat::from_str)]
     pub format: OutputFormat,
+
+    /// Number of most frequent values to list
+    #[arg(long, default_value_t = 5)]
+    pub top: usize,
+
+    /// Estimate distinct counts with HyperLogLog and frequent values with bounded
+    /// memory, for large files
+    #[arg(long)]
+    pub approx: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvSortOpts {
+    /// Input CSV file
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// Output file, "-" for stdout
+    #[arg(short, long, default_value = "-", value_parser = verify_output_format)]
+    pub output: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Column to sort by, e.g. "Kit Number:num:desc". Repeat for more columns
+    #[arg(long, value_name = "COLUMN[:num][:desc]", required = true)]
+    pub by: Vec<SortKey>,
+
+    /// Number of records sorted in memory before spilling to a temporary file
+    #[arg(long, default_value_t = 500_000)]
+    pub chunk_rows: usize,
+
+    /// format for output
+    #[arg(long, default_value = "csv", value_parser = OutputFormat::from_str)]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub writer: CsvWriterOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvDedupOpts {
+    /// Input CSV file
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// Output file, "-" for stdout
+    #[arg(short, long, default_value = "-", value_parser = verify_output_format)]
+    pub output: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Columns identifying a record, comma separated. Whole records are compared if not set
+    #[arg(long, value_delimiter = ',')]
+    pub key: Option<Vec<String>>,
+
+    /// format for output
+    #[arg(long, default_value = "csv", value_parser = OutputFormat::from_str)]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub writer: CsvWriterOpts,
+}
+
+#[derive(Debug, Args)]
+#[command(group(ArgGroup::new("split_by").required(true).args(["rows", "by_column"])))]
+pub struct CsvSplitOpts {
+    /// Input CSV file
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// Directory for the chunk files
+    #[arg(long, default_value = ".", value_parser = verify_path)]
+    pub dir: PathBuf,
+
+    /// Start of the chunk file names, the input file name by default
+    #[arg(long)]
+    pub prefix: Option<String>,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Maximum number of records per chunk file
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub rows: Option<u64>,
+
+    /// Write one chunk file per distinct value of this column
+    #[arg(long)]
+    pub by_column: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvMergeOpts {
+    /// CSV files to concatenate
+    #[arg(required = true, value_parser = verify_file)]
+    pub inputs: Vec<String>,
+
+    /// Output file, "-" for stdout
+    #[arg(short, long, default_value = "-", value_parser = verify_output_format)]
+    pub output: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// format for output
+    #[arg(long, default_value = "csv", value_parser = OutputFormat::from_str)]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub writer: CsvWriterOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvHeadOpts {
+    /// Input CSV file
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// Output file, "-" for stdout
+    #[arg(short, long, default_value = "-", value_parser = verify_output_format)]
+    pub output: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Number of records
+    #[arg(short = 'n', long, default_value_t = 10)]
+    pub rows: usize,
+
+    /// format for output
+    #[arg(long, default_value = "csv", value_parser = OutputFormat::from_str)]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub writer: CsvWriterOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvTailOpts {
+    /// Input CSV file
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// Output file, "-" for stdout
+    #[arg(short, long, default_value = "-", value_parser = verify_output_format)]
+    pub output: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Number of records
+    #[arg(short = 'n', long, default_value_t = 10)]
+    pub rows: usize,
+
+    /// format for output
+    #[arg(long, default_value = "csv", value_parser = OutputFormat::from_str)]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub writer: CsvWriterOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvSampleOpts {
+    /// Input CSV file
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// Output file, "-" for stdout
+    #[arg(short, long, default_value = "-", value_parser = verify_output_format)]
+    pub output: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Number of records
+    #[arg(short = 'n', long, default_value_t = 10)]
+    pub rows: usize,
+
+    /// Seed of the random generator, the same seed picks the same records
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// format for output
+    #[arg(long, default_value = "csv", value_parser = OutputFormat::from_str)]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub writer: CsvWriterOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvMaskOpts {
+    /// Input CSV file
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// Output file, "-" for stdout
+    #[arg(short, long, default_value = "-", value_parser = verify_output_format)]
+    pub output: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Columns to mask, comma separated
+    #[arg(long, value_name = "COLUMNS", value_delimiter = ',', required = true)]
+    pub mask_columns: Vec<String>,
+
+    /// BLAKE3 key file, needed by the token and fake modes
+    #[arg(short, long, value_parser = verify_file)]
+    pub key: Option<String>,
+
+    /// How values are masked: token, redact, partial or fake
+    #[arg(long, default_value = "token", value_parser = MaskMode::from_str)]
+    pub mode: MaskMode,
+
+    /// format for output
+    #[arg(long, default_value = "csv", value_parser = OutputFormat::from_str)]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub writer: CsvWriterOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvEncryptOpts {
+    /// Input CSV file
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// Output file, "-" for stdout
+    #[arg(short, long, default_value = "-", value_parser = verify_output_format)]
+    pub output: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Columns to encrypt, comma separated
+    #[arg(long, value_name = "COLUMNS", value_delimiter = ',', required = true)]
+    pub encrypt_columns: Vec<String>,
+
+    /// ChaCha20-Poly1305 key file
+    #[arg(short, long, value_parser = verify_file)]
+    pub key: String,
+
+    /// format for output
+    #[arg(long, default_value = "csv", value_parser = OutputFormat::from_str)]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub writer: CsvWriterOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvDecryptOpts {
+    /// Input CSV file
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// Output file, "-" for stdout
+    #[arg(short, long, default_value = "-", value_parser = verify_output_format)]
+    pub output: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Columns to decrypt, comma separated
+    #[arg(long, value_name = "COLUMNS", value_delimiter = ',', required = true)]
+    pub decrypt_columns: Vec<String>,
+
+    /// ChaCha20-Poly1305 key file
+    #[arg(short, long, value_parser = verify_file)]
+    pub key: String,
+
+    /// format for output
+    #[arg(long, default_value = "csv", value_parser = OutputFormat::from_str)]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub writer: CsvWriterOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvFakeOpts {
+    /// Schema file (yaml/toml/json) describing the columns
+    #[arg(long, value_parser = verify_file)]
+    pub schema: String,
+
+    /// Output file, "-" for stdout
+    #[arg(short, long, default_value = "-", value_parser = verify_output_format)]
+    pub output: String,
+
+    /// Number of records
+    #[arg(short = 'n', long, default_value_t = 100)]
+    pub rows: usize,
+
+    /// Seed of the random generator, the same seed gives the same records
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Date ages are counted up to, e.g. 2019-06-30. Today if not set, or
+    /// 2019-06-30 with --seed
+    #[arg(long, value_parser = parse_date)]
+    pub as_of: Option<NaiveDate>,
+
+    /// format for output. Detected from the output file extension if not set,
+    /// csv for stdout
+    #[arg(long, value_parser = OutputFormat::from_str)]
+    pub format: Option<OutputFormat>,
+
+    #[command(flatten)]
+    pub writer: CsvWriterOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvJoinOpts {
+    /// Left CSV file, streamed
+    #[arg(value_parser = verify_file)]
+    pub left: String,
+
+    /// Right CSV file, loaded in memory
+    #[arg(value_parser = verify_file)]
+    pub right: String,
+
+    /// Output file, "-" for stdout
+    #[arg(short, long, default_value = "-", value_parser = verify_output_format)]
+    pub output: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// Key column, or "LEFT=RIGHT" when the names differ
+    #[arg(long, value_name = "COLUMN")]
+    pub on: JoinOn,
+
+    /// Kind of join: inner, left or outer
+    #[arg(long, default_value = "inner", value_parser = JoinKind::from_str)]
+    pub kind: JoinKind,
+
+    /// format for output
+    #[arg(long, default_value = "csv", value_parser = OutputFormat::from_str)]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub writer: CsvWriterOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvQueryOpts {
+    /// SQL query, e.g. "SELECT Position, count(*) FROM juventus GROUP BY Position"
+    pub sql: String,
+
+    /// CSV file registered as a table, named after the file stem or given as
+    /// "NAME=PATH". Repeat for more tables
+    #[arg(short, long, value_name = "[NAME=]PATH", required = true)]
+    pub input: Vec<QueryTable>,
+
+    /// Output file, "-" for stdout
+    #[arg(short, long, default_value = "-", value_parser = verify_output_format)]
+    pub output: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// format for output
+    #[arg(long, default_value = "txt", value_parser = OutputFormat::from_str)]
+    pub format: OutputFormat,
+
+    #[command(flatten)]
+    pub writer: CsvWriterOpts,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvDiffOpts {
+    /// Previous CSV file
+    #[arg(value_parser = verify_file)]
+    pub old: String,
+
+    /// New CSV file
+    #[arg(value_parser = verify_file)]
+    pub new: String,
+
+    /// Columns identifying a row, comma separated
+    #[arg(long, value_delimiter = ',', required = true)]
+    pub key: Vec<String>,
+
+    /// Output file, "-" for stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// format for output: txt for a colored report, or json
+    #[arg(long, default_value = "txt", value_parser = ReportFormat::from_str)]
+    pub format: ReportFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvValidateOpts {
+    /// Input CSV file
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// Schema file in yaml, toml or json
+    #[arg(long, value_parser = verify_file)]
+    pub schema: String,
+
+    /// Output file, "-" for stdout
+    #[arg(short, long, default_value = "-")]
+    pub output: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// format for output: txt or json
+    #[arg(long, default_value = "txt", value_parser = ReportFormat::from_str)]
+    pub format: ReportFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct CsvSchemaInferOpts {
+    /// Input CSV file
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// Output file, "-" for stdout
+    #[arg(short, long, default_value = "-", value_parser = verify_output_format)]
+    pub output: String,
+
+    #[command(flatten)]
+    pub reader: CsvReaderOpts,
+
+    /// format for output: yaml, toml or json. Detected from the output file
+    /// extension if not set, yaml for stdout
+    #[arg(long, value_parser = OutputFormat::from_str)]
+    pub format: Option<OutputFormat>,
+
+    /// Text columns with at most this many distinct values get them as an enum
+    #[arg(long, default_value_t = 10)]
+    pub max_enum: usize,
 }
 
 impl CmdExector for CsvOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        match self.cmd {
+            Some(cmd) => cmd.execute().await,
+            None => self.convert.execute().await,
+        }
+    }
+}
+
+impl CmdExector for CsvFromOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let format = match self.format {
+            Some(format) => format,
+            None => format_from_path(&self.input)
+                .map_err(|_| anyhow::anyhow!("Cannot detect input format, use --format"))?,
+        };
+        process_csv_from(
+            &self.input,
+            &self.output,
+            format,
+            self.key.as_deref(),
+            self.delimiter,
+            self.flatten,
+        )
+    }
+}
+
+impl CmdExector for CsvStatsOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        report_encoding(&[&self.input], &self.reader)?;
+        process_csv_stats(
+            &self.input,
+            &self.output,
+            self.format,
+            &self.reader,
+            self.top,
+            self.approx,
+        )
+    }
+}
+
+impl CmdExector for CsvSortOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        report_encoding(&[&self.input], &self.reader)?;
+        process_csv_sort(
+            &self.input,
+            &self.output,
+            self.format,
+            &self.reader,
+            &self.by,
+            self.chunk_rows,
+            &self.writer,
+        )
+    }
+}
+
+impl CmdExector for CsvDedupOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        report_encoding(&[&self.input], &self.reader)?;
+        process_csv_dedup(
+            &self.input,
+            &self.output,
+            self.format,
+            &self.reader,
+            self.key.as_deref(),
+            &self.writer,
+        )
+    }
+}
+
+impl CmdExector for CsvSplitOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        report_encoding(&[&self.input], &self.reader)?;
+        let by = match (self.rows, &self.by_column) {
+            (Some(rows), _) => SplitBy::Rows(rows as usize),
+            (None, Some(column)) => SplitBy::Column(column),
+            (None, None) => unreachable!("--rows or --by-column is required"),
+        };
+        let chunks = process_csv_split(
+            &self.input,
+            &self.dir,
+            self.prefix.as_deref(),
+            &self.reader,
+            by,
+        )?;
+        for (path, rows) in chunks {
+            println!("{}: {} rows", path.display(), rows);
+        }
+        Ok(())
+    }
+}
+
+impl CmdExector for CsvMergeOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let inputs = self.inputs.iter().map(String::as_str).collect::<Vec<_>>();
+        report_encoding(&inputs, &self.reader)?;
+        process_csv_merge(
+            &self.inputs,
+            &self.output,
+            self.format,
+            &self.reader,
+            &self.writer,
+        )
+    }
+}
+
+impl CmdExector for CsvHeadOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        report_encoding(&[&self.input], &self.reader)?;
+        process_csv_head(
+            &self.input,
+            &self.output,
+            self.format,
+            &self.reader,
+            self.rows,
+            &self.writer,
+        )
+    }
+}
+
+impl CmdExector for CsvTailOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        report_encoding(&[&self.input], &self.reader)?;
+        process_csv_tail(
+            &self.input,
+            &self.output,
+            self.format,
+            &self.reader,
+            self.rows,
+            &self.writer,
+        )
+    }
+}
+
+impl CmdExector for CsvSampleOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        report_encoding(&[&self.input], &self.reader)?;
+        process_csv_sample(
+            &self.input,
+            &self.output,
+            self.format,
+            &self.reader,
+            self.rows,
+            self.seed,
+            &self.writer,
+        )
+    }
+}
+
+impl CmdExector for CsvMaskOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        report_encoding(&[&self.input], &self.reader)?;
+        process_csv_mask(
+            &self.input,
+            &self.output,
+            self.format,
+            &self.reader,
+            &self.mask_columns,
+            self.mode,
+            self.key.as_deref(),
+            &self.writer,
+        )
+    }
+}
+
+impl CmdExector for CsvEncryptOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        report_encoding(&[&self.input], &self.reader)?;
+        process_csv_encrypt(
+            &self.input,
+            &self.output,
+            self.format,
+            &self.reader,
+            &self.encrypt_columns,
+            &self.key,
+            &self.writer,
+        )
+    }
+}
+
+impl CmdExector for CsvDecryptOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        report_encoding(&[&self.input], &self.reader)?;
+        process_csv_decrypt(
+            &self.input,
+            &self.output,
+            self.format,
+            &self.reader,
+            &self.decrypt_columns,
+            &self.key,
+            &self.writer,
+        )
+    }
+}
+
+impl CmdExector for CsvFakeOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let format = match self.format {
+            Some(format) => format,
+            None if self.output == "-" => OutputFormat::Csv,
+            None => format_from_path(&self.output).map_err(|e| anyhow::anyhow!(e))?,
+        };
+        process_csv_fake(
+            &self.schema,
+            &self.output,
+            format,
+            self.rows,
+            self.seed,
+            self.as_of,
+            &self.writer,
+        )
+    }
+}
+
+impl CmdExector for CsvJoinOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        report_encoding(&[&self.left, &self.right], &self.reader)?;
+        process_csv_join(
+            &self.left,
+            &self.right,
+            &self.output,
+            self.format,
+            &self.reader,
+            &self.on,
+            self.kind,
+            &self.writer,
+        )
+    }
+}
+
+impl CmdExector for CsvQueryOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let paths = self
+            .input
+            .iter()
+            .map(|t| t.path.as_str())
+            .collect::<Vec<_>>();
+        report_encoding(&paths, &self.reader)?;
+        let sources = self
+            .input
+            .iter()
+            .map(|t| QuerySource {
+                name: t.name.as_deref(),
+                path: &t.path,
+            })
+            .collect::<Vec<_>>();
+        process_csv_query(
+            &self.sql,
+            &sources,
+            &self.output,
+            self.format,
+            &self.reader,
+            &self.writer,
+        )
+    }
+}
+
+impl CmdExector for CsvDiffOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        report_encoding(&[&self.old, &self.new], &self.reader)?;
+        process_csv_diff(
+            &self.old,
+            &self.new,
+            &self.output,
+            self.format,
+            &self.reader,
+            &self.key,
+        )
+    }
+}
+
+impl CmdExector for CsvValidateOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        report_encoding(&[&self.input], &self.reader)?;
+        process_csv_validate(
+            &self.input,
+            &self.schema,
+            &self.output,
+            self.format,
+            &self.reader,
+        )
+    }
+}
+
+impl CmdExector for CsvSchemaInferOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        report_encoding(&[&self.input], &self.reader)?;
+        let format = match self.format {
+            Some(format) => format,
+            None if self.output == "-" => OutputFormat::Yaml,
+            None => format_from_path(&self.output).map_err(|e| anyhow::anyhow!(e))?,
+        };
+        process_csv_schema_infer(
+            &self.input,
+            &self.output,
+            format,
+            &self.reader,
+            self.max_enum,
+        )
+    }
+}
+
+impl CmdExector for CsvConvertOpts {
     async fn execute(self) -> anyhow::Result<()> {
         let output = match self.output {
             Some(output) => output,
             None => format!("output.{}", self.format),
         };
-        process_csv(&self.input, &output, self.format)
+        let input = self
+            .input
+            .ok_or_else(|| anyhow::anyhow!("Missing input, see `rcli csv --help`"))?;
+        report_encoding(&[&input], &self.reader)?;
+        process_csv(
+            &input,
+            &output,
+            self.format,
+            &self.reader,
+            &self.types,
+            &self.filter,
+            &self.writer,
+        )
     }
 }
 
+/// With `--encoding auto`, tell which encoding was detected for every input file.
+/// Stdin can only be read once, so it is not looked at ahead of the command
+fn report_encoding(inputs: &[&str], opts: &CsvReaderOpts) -> anyhow::Result<()> {
+    if matches!(opts.encoding, CsvEncoding::Auto) {
+        for input in inputs.iter().filter(|input| **input != "-") {
+            let encoding = input_encoding(input)?;
+            eprintln!("Detected encoding of {}: {}", input, encoding.name());
+        }
+    }
+    Ok(())
+}
+
 /// Output format
 #[derive(Debug, Clone, Copy)]
 pub enum OutputFormat {
+    Csv,
     Json,
+    Ndjson,
     Yaml,
     Toml,
+    Markdown,
+    Html,
+    Text,
+}
+
+impl OutputFormat {
+    /// Formats rendered as a table, which cannot hold nested values
+    pub fn is_table(&self) -> bool {
+        matches!(
+            self,
+            OutputFormat::Markdown | OutputFormat::Html | OutputFormat::Text
+        )
+    }
+}
+
+/// Character encoding of CSV input
+#[derive(Debug, Clone, Copy)]
+pub enum CsvEncoding {
+    Auto,
+    Label(&'static Encoding),
+}
+
+/// Output format of reports, plain text or JSON
+#[derive(Debug, Clone, Copy)]
+pub enum ReportFormat {
+    Text,
+    Json,
+}
+
+/// Kind of join
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+    Outer,
+}
+
+/// How `csv mask` replaces values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaskMode {
+    /// Keyed BLAKE3 token, the same value always gives the same token
+    Token,
+    /// A fixed marker
+    Redact,
+    /// Keep the last 4 characters
+    Partial,
+    /// Random looking value with the same shape, derived from the key
+    Fake,
+}
+
+/// Column alignment of tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Auto,
+    Left,
+    Right,
+    Center,
+}
+
+/// Parse a single byte character, `\t` is accepted for tab
+fn parse_csv_char(s: &str) -> Result<u8, String> {
+    match s {
+        "\\t" => Ok(b'\t'),
+        _ => match s.as_bytes() {
+            [b] if b.is_ascii() => Ok(*b),
+            _ => Err(format!("Expected a single ASCII character: {}", s)),
+        },
+    }
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|e| format!("Invalid date {}: {}", s, e))
 }
 
 fn verify_output_format(output: &str) -> Result<String, String> {
-    let format = output.to_lowercase();
+    if output != "-" {
+        format_from_path(output)?;
+    }
+    Ok(output.to_string())
+}
+
+/// Detect the format from the file extension, past a compression suffix like `.gz`
+fn format_from_path(path: &str) -> Result<OutputFormat, String> {
+    let format = split_compression(path).0.to_lowercase();
     let Some(last_dot_index) = format.rfind('.') else {
-        return Err(format!("Invalid format: {}", output));
+        return Err(format!("Invalid format: {}", path));
     };
-    let ext = &format[last_dot_index + 1..];
-    let _: OutputFormat = ext.parse()?;
-    Ok(output.to_string())
+    format[last_dot_index + 1..].parse()
 }
 
 impl FromStr for OutputFormat {
@@ -63,20 +1209,114 @@ impl FromStr for OutputFormat {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let format = s.to_lowercase();
         match format.as_str() {
+            "csv" => Ok(OutputFormat::Csv),
             "json" => Ok(OutputFormat::Json),
-            "yaml" => Ok(OutputFormat::Yaml),
+            "ndjson" | "jsonl" => Ok(OutputFormat::Ndjson),
+            "yaml" | "yml" => Ok(OutputFormat::Yaml),
             "toml" => Ok(OutputFormat::Toml),
+            "md" | "markdown" => Ok(OutputFormat::Markdown),
+            "html" | "htm" => Ok(OutputFormat::Html),
+            "txt" | "text" => Ok(OutputFormat::Text),
             _ => Err(format!("Invalid format: {}", s)),
         }
     }
 }
 
+impl FromStr for ColumnTypeOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((name, column_type)) = s.rsplit_once('=') else {
+            return Err(format!("Expected COLUMN=TYPE: {}", s));
+        };
+        Ok(Self {
+            name: name.to_string(),
+            column_type: column_type.parse()?,
+        })
+    }
+}
+
+impl FromStr for ColumnRename {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((from, to)) = s.split_once('=') else {
+            return Err(format!("Expected OLD=NEW: {}", s));
+        };
+        Ok(Self {
+            from: from.to_string(),
+            to: to.to_string(),
+        })
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut key = Self {
+            column: s.to_string(),
+            numeric: false,
+            desc: false,
+        };
+        // flags are peeled off the end, so column names may contain ':'
+        while let Some((column, flag)) = key.column.rsplit_once(':') {
+            match flag {
+                "num" => key.numeric = true,
+                "desc" => key.desc = true,
+                "asc" => {}
+                _ => break,
+            }
+            key.column = column.to_string();
+        }
+        if key.column.is_empty() {
+            return Err(format!("Missing column: {}", s));
+        }
+        Ok(key)
+    }
+}
+
+impl FromStr for JoinOn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (left, right) = s.split_once('=').unwrap_or((s, s));
+        if left.is_empty() || right.is_empty() {
+            return Err(format!("Expected COLUMN or LEFT=RIGHT: {}", s));
+        }
+        Ok(Self {
+            left: left.to_string(),
+            right: right.to_string(),
+        })
+    }
+}
+
+impl FromStr for QueryTable {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, path) = match s.split_once('=') {
+            Some((name, path)) if !std::path::Path::new(s).exists() => (Some(name), path),
+            _ => (None, s),
+        };
+        Ok(Self {
+            name: name.map(|n| n.to_string()),
+            path: verify_file(path)?,
+        })
+    }
+}
+
 impl From<OutputFormat> for &str {
     fn from(format: OutputFormat) -> Self {
         match format {
+            OutputFormat::Csv => "csv",
             OutputFormat::Json => "json",
+            OutputFormat::Ndjson => "ndjson",
             OutputFormat::Yaml => "yaml",
             OutputFormat::Toml => "toml",
+            OutputFormat::Markdown => "md",
+            OutputFormat::Html => "html",
+            OutputFormat::Text => "txt",
         }
     }
 }
@@ -86,3 +1326,143 @@ impl Display for OutputFormat {
         write!(f, "{}", Into::<&str>::into(*self))
     }
 }
+
+impl FromStr for Align {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(Align::Auto),
+            "left" => Ok(Align::Left),
+            "right" => Ok(Align::Right),
+            "center" => Ok(Align::Center),
+            _ => Err(format!("Invalid alignment: {}", s)),
+        }
+    }
+}
+
+impl Display for Align {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Align::Auto => write!(f, "auto"),
+            Align::Left => write!(f, "left"),
+            Align::Right => write!(f, "right"),
+            Align::Center => write!(f, "center"),
+        }
+    }
+}
+
+impl FromStr for CsvEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(CsvEncoding::Auto);
+        }
+        Encoding::for_label(s.as_bytes())
+            .map(CsvEncoding::Label)
+            .ok_or_else(|| format!("Unknown encoding: {}", s))
+    }
+}
+
+impl Display for CsvEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CsvEncoding::Auto => write!(f, "auto"),
+            CsvEncoding::Label(encoding) => write!(f, "{}", encoding.name()),
+        }
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "txt" | "text" => Ok(ReportFormat::Text),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(format!("Invalid format: {}", s)),
+        }
+    }
+}
+
+impl Display for ReportFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReportFormat::Text => write!(f, "txt"),
+            ReportFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl FromStr for JoinKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "inner" => Ok(JoinKind::Inner),
+            "left" => Ok(JoinKind::Left),
+            "outer" | "full" => Ok(JoinKind::Outer),
+            _ => Err(format!("Invalid join kind: {}", s)),
+        }
+    }
+}
+
+impl Display for JoinKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JoinKind::Inner => write!(f, "inner"),
+            JoinKind::Left => write!(f, "left"),
+            JoinKind::Outer => write!(f, "outer"),
+        }
+    }
+}
+
+impl FromStr for MaskMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "token" => Ok(MaskMode::Token),
+            "redact" => Ok(MaskMode::Redact),
+            "partial" => Ok(MaskMode::Partial),
+            "fake" => Ok(MaskMode::Fake),
+            _ => Err(format!("Invalid mask mode: {}", s)),
+        }
+    }
+}
+
+impl Display for MaskMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MaskMode::Token => write!(f, "token"),
+            MaskMode::Redact => write!(f, "redact"),
+            MaskMode::Partial => write!(f, "partial"),
+            MaskMode::Fake => write!(f, "fake"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv_char() {
+        assert_eq!(parse_csv_char(";"), Ok(b';'));
+        assert_eq!(parse_csv_char("\\t"), Ok(b'\t'));
+        assert!(parse_csv_char("ab").is_err());
+        assert!(parse_csv_char("é").is_err());
+    }
+
+    #[test]
+    fn test_parse_sort_key() {
+        let key = "Kit Number:num:desc".parse::<SortKey>().unwrap();
+        assert_eq!(key.column, "Kit Number");
+        assert!(key.numeric && key.desc);
+        let key = "a:b".parse::<SortKey>().unwrap();
+        assert_eq!(key.column, "a:b");
+        assert!(!key.numeric && !key.desc);
+        assert!(":desc".parse::<SortKey>().is_err());
+    }
+}
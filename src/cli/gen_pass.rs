@@ -1,6 +1,10 @@
-use crate::{process_gen_pass, CmdExector};
-use clap::Args;
-use zxcvbn::zxcvbn;
+use crate::{
+    gen_with_min_score, load_wordlist, passphrase_entropy, process_gen_passphrase,
+    process_gen_password, CmdExector, PasswordPolicy, PasswordStrength,
+};
+use clap::{ArgAction, Args};
+
+use super::verify_file;
 
 #[derive(Debug, Args)]
 #[command(name = "genpass", about = "generate password")]
@@ -24,20 +28,136 @@ pub struct GenPassOpts {
     /// Include symbol characters
     #[arg(short, long, default_value_t = false)]
     pub symbol: bool,
+
+    /// Symbols to use instead of the default ones, e.g. "-_.!". Enables symbols
+    #[arg(long, allow_hyphen_values = true)]
+    pub symbols: Option<String>,
+
+    /// Only use these characters instead of the character classes, ambiguous
+    /// ones are still left out unless --exclude-ambiguous is false
+    #[arg(long, conflicts_with_all = ["uppercase", "number", "symbol", "symbols"])]
+    pub charset: Option<String>,
+
+    /// Characters never to use
+    #[arg(long, default_value = "")]
+    pub exclude: String,
+
+    /// Leave out the easily confused characters 0, O, o and l
+    #[arg(long, default_value_t = true, action = ArgAction::Set, num_args = 0..=1, default_missing_value = "true")]
+    pub exclude_ambiguous: bool,
+
+    /// Minimum number of uppercase letters
+    #[arg(long, default_value_t = 0)]
+    pub min_upper: usize,
+
+    /// Minimum number of lowercase letters
+    #[arg(long, default_value_t = 0)]
+    pub min_lower: usize,
+
+    /// Minimum number of digits
+    #[arg(long, default_value_t = 0)]
+    pub min_digits: usize,
+
+    /// Minimum number of symbols
+    #[arg(long, default_value_t = 0)]
+    pub min_symbols: usize,
+
+    /// Generate a passphrase of this many words instead of characters
+    #[arg(short, long)]
+    pub words: Option<usize>,
+
+    /// Separator between the words of a passphrase
+    #[arg(long, default_value = "-", requires = "words")]
+    pub separator: String,
+
+    /// Capitalize the words of a passphrase
+    #[arg(long, requires = "words")]
+    pub capitalize: bool,
+
+    /// Wordlist file, one word per line or in the EFF format. The embedded list if not set
+    #[arg(long, value_parser = verify_file, requires = "words")]
+    pub wordlist: Option<String>,
+
+    /// Regenerate until the zxcvbn score (0 to 4) is at least this
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=4))]
+    pub min_score: u8,
+
+    /// Give up on --min-score after this many passwords
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_attempts: u64,
+
+    /// Number of passwords to generate
+    #[arg(short, long, default_value_t = 1)]
+    pub count: usize,
+
+    /// Print the passwords with their score, guesses, entropy and crack times as JSON
+    #[arg(long)]
+    pub json: bool,
 }
 
 impl CmdExector for GenPassOpts {
     async fn execute(self) -> anyhow::Result<()> {
-        let password = process_gen_pass(
-            self.length,
-            self.uppercase,
-            self.lowercase,
-            self.number,
-            self.symbol,
-        )?;
-        let result = zxcvbn(&password, &[]);
-        eprintln!("Password strength: {}", result.score());
-        println!("{}", password);
+        let hint = match self.words {
+            Some(_) => "Use more words or a larger wordlist",
+            None => "Use a longer password or more character classes",
+        };
+        let (mut generate, entropy): (Box<dyn FnMut() -> anyhow::Result<String>>, f64) =
+            match self.words {
+                Some(words) => {
+                    let wordlist = load_wordlist(self.wordlist.as_deref())?;
+                    let entropy = passphrase_entropy(words, wordlist.len());
+                    let separator = self.separator;
+                    let capitalize = self.capitalize;
+                    let generate =
+                        move || process_gen_passphrase(words, &separator, capitalize, &wordlist);
+                    (Box::new(generate), entropy)
+                }
+                None => {
+                    let policy = PasswordPolicy {
+                        length: self.length,
+                        upper: self.uppercase,
+                        lower: self.lowercase,
+                        number: self.number,
+                        symbol: self.symbol || self.symbols.is_some(),
+                        symbols: self.symbols,
+                        charset: self.charset,
+                        exclude: self.exclude,
+                        exclude_ambiguous: self.exclude_ambiguous,
+                        min_upper: self.min_upper,
+                        min_lower: self.min_lower,
+                        min_digits: self.min_digits,
+                        min_symbols: self.min_symbols,
+                    };
+                    let entropy = policy.entropy()?;
+                    (Box::new(move || process_gen_password(&policy)), entropy)
+                }
+            };
+
+        let mut reports = Vec::new();
+        for _ in 0..self.count {
+            let (password, result) = gen_with_min_score(
+                self.min_score,
+                self.max_attempts as usize,
+                hint,
+                &mut generate,
+            )?;
+            if self.json {
+                let mut strength = PasswordStrength::new(&result);
+                strength.password = Some(password);
+                strength.entropy_bits = Some(entropy);
+                reports.push(strength);
+            } else {
+                eprintln!(
+                    "Password strength: {}, entropy: {:.1} bits",
+                    result.score(),
+                    entropy
+                );
+                println!("{}", password);
+            }
+        }
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&reports)?);
+        }
         Ok(())
     }
 }
@@ -2,15 +2,23 @@ mod b64;
 mod csv;
 mod gen_pass;
 mod http_serve;
+mod pass_check;
 mod text;
 
-pub use self::csv::CsvOpts;
+pub use self::csv::{
+    ColumnRename, ColumnTypeOverride, CsvConvertOpts, CsvDecryptOpts, CsvDedupOpts, CsvDiffOpts,
+    CsvEncryptOpts, CsvFakeOpts, CsvFilterOpts, CsvFromOpts, CsvHeadOpts, CsvJoinOpts, CsvMaskOpts,
+    CsvMergeOpts, CsvOpts, CsvQueryOpts, CsvReaderOpts, CsvSampleOpts, CsvSchemaInferOpts,
+    CsvSchemaSubCmd, CsvSortOpts, CsvSplitOpts, CsvStatsOpts, CsvSubCmd, CsvTailOpts, CsvTypeOpts,
+    CsvValidateOpts, CsvWriterOpts, JoinOn, QueryTable, SortKey,
+};
 pub use b64::{Base64DecodeOpts, Base64EncodeOpts};
 pub use b64::{Base64Format, Base64SubCmd};
-pub use csv::OutputFormat;
+pub use csv::{Align, CsvEncoding, JoinKind, MaskMode, OutputFormat, ReportFormat};
 use enum_dispatch::enum_dispatch;
 pub use gen_pass::GenPassOpts;
 pub use http_serve::HttpServeSubCmd;
+pub use pass_check::PassCheckOpts;
 use std::path::{Path, PathBuf};
 pub use text::{
     TextDecryptOpts, TextEncryptFormat, TextEncryptOpts, TextGenerateOpts, TextSignFormat,
@@ -31,9 +39,11 @@ pub struct CliOpts {
 #[enum_dispatch(CmdExector)]
 pub enum SubCmd {
     #[command(about = "csv subcommand")]
-    Csv(CsvOpts),
+    Csv(Box<CsvOpts>),
     #[command(name = "genpass", about = "generate password")]
     GenPass(GenPassOpts),
+    #[command(name = "passcheck", about = "check the strength of existing passwords")]
+    PassCheck(PassCheckOpts),
     #[command(subcommand, about = "Base64 encode/decode subcommand")]
     Base64(Base64SubCmd),
     #[command(subcommand, about = "Text sign/verify subcommand")]
@@ -0,0 +1,73 @@
+use crate::{process_pass_check, CmdExector, ReportFormat};
+use clap::Args;
+use std::str::FromStr;
+
+use super::verify_file;
+
+#[derive(Debug, Args)]
+pub struct PassCheckOpts {
+    /// File with one password per line, "-" for stdin
+    #[arg(short, long, value_parser = verify_file, default_value = "-")]
+    pub input: String,
+
+    /// Words an attacker would try first, like user or company names, comma separated
+    #[arg(long, value_delimiter = ',')]
+    pub user_inputs: Vec<String>,
+
+    /// Fail when a password scores below this (0 to 4)
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u8).range(0..=4))]
+    pub min_score: u8,
+
+    /// Print the passwords next to their report, they are left out by default
+    #[arg(long)]
+    pub show: bool,
+
+    /// Format of the report: txt or json
+    #[arg(long, default_value = "txt", value_parser = ReportFormat::from_str)]
+    pub format: ReportFormat,
+}
+
+impl CmdExector for PassCheckOpts {
+    async fn execute(self) -> anyhow::Result<()> {
+        let checks = process_pass_check(&self.input, &self.user_inputs, self.show)?;
+        match self.format {
+            ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&checks)?),
+            ReportFormat::Text => {
+                for check in &checks {
+                    let strength = &check.strength;
+                    let crack_time = &strength.crack_times.offline_slow_hashing_1e4_per_second;
+                    match &strength.password {
+                        Some(password) => print!("line {} ({}): ", check.line, password),
+                        None => print!("line {}: ", check.line),
+                    }
+                    println!(
+                        "score {}, 10^{:.1} guesses, {} to crack offline",
+                        strength.score, strength.guesses_log10, crack_time.display
+                    );
+                    if let Some(warning) = &strength.warning {
+                        println!("  warning: {}", warning);
+                    }
+                    for suggestion in &strength.suggestions {
+                        println!("  suggestion: {}", suggestion);
+                    }
+                }
+            }
+        }
+        let weak = checks
+            .iter()
+            .filter(|c| c.strength.score < self.min_score)
+            .count();
+        if matches!(self.format, ReportFormat::Text) {
+            println!(
+                "{} passwords checked, {} below score {}",
+                checks.len(),
+                weak,
+                self.min_score
+            );
+        }
+        if weak > 0 {
+            anyhow::bail!("{} passwords scored below {}", weak, self.min_score);
+        }
+        Ok(())
+    }
+}
@@ -10,6 +10,7 @@ async fn main() -> Result<()> {
     match cli.subcmd {
         SubCmd::Csv(opts) => opts.execute().await,
         SubCmd::GenPass(opts) => opts.execute().await,
+        SubCmd::PassCheck(opts) => opts.execute().await,
         SubCmd::Base64(subcmd) => subcmd.execute().await,
         SubCmd::Text(subcmd) => subcmd.execute().await,
         SubCmd::Http(cmd) => cmd.execute().await,
@@ -1,38 +1,272 @@
 use anyhow::Result;
-use csv::Reader;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::fs;
-
-use crate::OutputFormat;
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "PascalCase")]
-struct Player {
-    name: String,
-    position: String,
-    #[serde(rename = "DOB")]
-    dob: String,
-    nationality: String,
-    #[serde(rename = "Kit Number")]
-    kit: u8,
-}
-
-pub fn process_csv(input: &str, output: &str, format: OutputFormat) -> Result<()> {
-    let mut reader = Reader::from_path(input)?;
-    let headers = reader.headers()?.clone();
-    let mut records = Vec::new();
-    for record in reader.records() {
-        let record = record?;
-        let value = headers.iter().zip(record.iter()).collect::<Value>();
-        records.push(value);
-    }
-
-    let serde_content = match format {
-        OutputFormat::Json => serde_json::to_string_pretty(&records)?,
-        OutputFormat::Yaml => serde_yaml::to_string(&records)?,
-        OutputFormat::Toml => toml::to_string(&records.first())?,
+use csv::{Reader, ReaderBuilder, StringRecord};
+use serde_json::{Map, Value};
+use std::io::Read;
+
+use crate::{
+    decode_reader, filter_headers, filter_record, get_data_reader, infer_column_types,
+    output_writer, typed_values, verify_filter_columns, ColumnTypeOverride, CsvFilterOpts,
+    CsvReaderOpts, CsvSchema, CsvTypeOpts, CsvWriterOpts, OutputFormat, SchemaValidator,
+};
+
+/// Number of records looked at to infer types when streaming
+const INFER_SAMPLE_ROWS: usize = 1000;
+
+/// Records of a CSV file converted to JSON objects
+pub type RecordIter = Box<dyn Iterator<Item = Result<Value>>>;
+
+pub fn process_csv(
+    input: &str,
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    type_opts: &CsvTypeOpts,
+    filter_opts: &CsvFilterOpts,
+    writer_opts: &CsvWriterOpts,
+) -> Result<()> {
+    let stream = writer_opts.stream || matches!(format, OutputFormat::Ndjson);
+    let sample_rows = if stream {
+        INFER_SAMPLE_ROWS
+    } else {
+        usize::MAX
+    };
+    let (headers, records) = csv_records(input, opts, type_opts, sample_rows)?;
+    verify_filter_columns(&headers, filter_opts)?;
+    let mut writer = output_writer(output, format, writer_opts)?;
+    writer.write_headers(&filter_headers(&headers, filter_opts))?;
+    for record in records {
+        if let Some(record) = filter_record(record?, filter_opts) {
+            writer.write_record(&record)?;
+        }
+    }
+    writer.finish()
+}
+
+/// Read every record of a CSV file as a JSON object
+pub fn read_records(
+    input: &str,
+    opts: &CsvReaderOpts,
+    type_opts: &CsvTypeOpts,
+) -> Result<Vec<Value>> {
+    csv_records(input, opts, type_opts, usize::MAX)?.1.collect()
+}
+
+/// Lazily read the records of a CSV file as JSON objects, along with the column names.
+/// Type inference looks at the first `sample_rows` records, which are the only ones
+/// held in memory
+pub fn csv_records(
+    input: &str,
+    opts: &CsvReaderOpts,
+    type_opts: &CsvTypeOpts,
+    sample_rows: usize,
+) -> Result<(Vec<String>, RecordIter)> {
+    let mut reader = csv_input(input, opts)?;
+    let headers = csv_headers(&mut reader, opts)?;
+    let mut type_opts = type_opts.clone();
+    let validator = match type_opts.schema.clone() {
+        Some(schema) => {
+            let header_line = header_line(&mut reader, opts)?;
+            Some(schema_validator(
+                &schema,
+                &headers,
+                header_line,
+                &mut type_opts,
+            )?)
+        }
+        None => None,
+    };
+    let mut records = reader.into_records();
+    let sample = if type_opts.infer_types {
+        records
+            .by_ref()
+            .take(sample_rows)
+            .collect::<Result<Vec<_>, _>>()?
+    } else {
+        Vec::new()
     };
+    let types = infer_column_types(&headers, &sample, &type_opts)?;
+    let columns = headers.clone();
+    let iter = sample
+        .into_iter()
+        .map(Ok)
+        .chain(records)
+        .map(move |record| {
+            let record = record?;
+            if let Some(violation) = validator.iter().flat_map(|v| v.validate(&record)).next() {
+                anyhow::bail!("Schema violation at {}", violation);
+            }
+            let values = typed_values(&headers, &types, &record, &type_opts)?;
+            Ok(record_to_value(&headers, values))
+        });
+    Ok((columns, Box::new(iter)))
+}
+
+/// Bind the schema to the header, its column types are used unless overridden
+fn schema_validator(
+    schema: &str,
+    headers: &[String],
+    header_line: Option<u64>,
+    type_opts: &mut CsvTypeOpts,
+) -> Result<SchemaValidator> {
+    let schema = CsvSchema::load(schema)?;
+    let (validator, violations) = SchemaValidator::new(&schema, headers, header_line)?;
+    if let Some(violation) = violations.first() {
+        anyhow::bail!("Schema violation at {}", violation);
+    }
+    for (name, column_type) in validator.column_types() {
+        if !type_opts.types.iter().any(|t| t.name == name) {
+            type_opts.types.push(ColumnTypeOverride {
+                name: name.to_string(),
+                column_type,
+            });
+        }
+    }
+    type_opts.date_formats.extend(schema.date_formats);
+    Ok(validator)
+}
 
-    fs::write(output, serde_content)?;
-    Ok(())
+/// Open a CSV file, decompressed and decoded to UTF-8
+pub fn csv_input(input: &str, opts: &CsvReaderOpts) -> Result<Reader<Box<dyn Read>>> {
+    let reader = decode_reader(get_data_reader(input)?, opts.encoding)?;
+    Ok(csv_reader(reader, opts))
+}
+
+/// Build a csv reader with the given settings
+pub fn csv_reader<R: Read>(rdr: R, opts: &CsvReaderOpts) -> Reader<R> {
+    ReaderBuilder::new()
+        .delimiter(opts.delimiter)
+        .quote(opts.quote)
+        .escape(opts.escape)
+        .double_quote(opts.escape.is_none())
+        .comment(opts.comment)
+        .flexible(opts.flexible)
+        .has_headers(opts.header)
+        .from_reader(rdr)
+}
+
+/// Resolve the column names: `--columns` first, then the header row,
+/// otherwise `col0`, `col1`, ... based on the width of the first record
+pub fn csv_headers<R: Read>(reader: &mut Reader<R>, opts: &CsvReaderOpts) -> Result<Vec<String>> {
+    if let Some(columns) = &opts.columns {
+        return Ok(columns.clone());
+    }
+    // without a header row this peeks the first record, which is still yielded by `records()`
+    let first = reader.headers()?;
+    if opts.header {
+        Ok(first.iter().map(|h| h.to_string()).collect())
+    } else {
+        Ok((0..first.len()).map(column_name).collect())
+    }
+}
+
+/// Line of the header row, none without one or when `--columns` replaces it
+pub fn header_line<R: Read>(reader: &mut Reader<R>, opts: &CsvReaderOpts) -> Result<Option<u64>> {
+    if !opts.header || opts.columns.is_some() {
+        return Ok(None);
+    }
+    Ok(reader.headers()?.position().map(|p| p.line()))
+}
+
+/// Turn the fields of a record into a JSON object, extra fields get a synthesized name
+pub fn record_to_value(headers: &[String], values: Vec<Value>) -> Value {
+    let map = values
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let name = headers.get(i).cloned().unwrap_or_else(|| column_name(i));
+            (name, value)
+        })
+        .collect::<Map<_, _>>();
+    Value::Object(map)
+}
+
+/// Fields of a record as JSON strings
+pub fn string_values(record: &StringRecord) -> Vec<Value> {
+    record
+        .iter()
+        .map(|f| Value::String(f.to_string()))
+        .collect()
+}
+
+/// Position of a column in the header
+pub fn column_index(headers: &[String], name: &str) -> Result<usize> {
+    headers
+        .iter()
+        .position(|h| h == name)
+        .ok_or_else(|| anyhow::anyhow!("Unknown column: {}", name))
+}
+
+fn column_name(index: usize) -> String {
+    format!("col{}", index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_records, serialize_records};
+    use serde_json::json;
+
+    #[test]
+    fn test_toml_round_trip() -> Result<()> {
+        let type_opts = CsvTypeOpts {
+            infer_types: true,
+            ..Default::default()
+        };
+        let records = read_records("assets/juventus.csv", &Default::default(), &type_opts)?;
+        assert_eq!(records.len(), 27);
+
+        let writer_opts = CsvWriterOpts {
+            toml_key: "players".to_string(),
+            ..Default::default()
+        };
+        let content = serialize_records(&records, OutputFormat::Toml, &writer_opts)?;
+        assert!(content.starts_with("[[players]]"));
+        let parsed = parse_records(&content, OutputFormat::Toml, Some("players"))?;
+        assert_eq!(parsed, records);
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_reader_headerless_with_delimiter() -> Result<()> {
+        let opts = CsvReaderOpts {
+            delimiter: b';',
+            header: false,
+            ..Default::default()
+        };
+        let data = "Mattia Perin;Goalkeeper;37\nGianluigi Buffon;Goalkeeper;77\n";
+        let mut reader = csv_reader(data.as_bytes(), &opts);
+        let headers = csv_headers(&mut reader, &opts)?;
+        assert_eq!(headers, ["col0", "col1", "col2"]);
+
+        let records = reader
+            .records()
+            .map(|r| Ok(record_to_value(&headers, string_values(&r?))))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0],
+            json!({"col0": "Mattia Perin", "col1": "Goalkeeper", "col2": "37"})
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_headers_from_columns() -> Result<()> {
+        let opts = CsvReaderOpts {
+            delimiter: b'\t',
+            header: false,
+            comment: Some(b'#'),
+            columns: Some(vec!["name".to_string(), "kit".to_string()]),
+            ..Default::default()
+        };
+        let data = "# players\nMattia Perin\t37\n";
+        let mut reader = csv_reader(data.as_bytes(), &opts);
+        let headers = csv_headers(&mut reader, &opts)?;
+        let record = reader.records().next().unwrap()?;
+        assert_eq!(
+            record_to_value(&headers, string_values(&record)),
+            json!({"name": "Mattia Perin", "kit": "37"})
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,161 @@
+use anyhow::Result;
+use base64::prelude::*;
+
+use crate::{
+    map_columns, ChaCha20Poly1305Decryptor, ChaCha20Poly1305Encryptor, CsvReaderOpts,
+    CsvWriterOpts, KeyLoader, OutputFormat,
+};
+
+/// Encrypt every cell of some columns on its own to a base64url token. Each token has
+/// its own random nonce, so equal values give different tokens, and is bound to its
+/// column name, so it cannot be moved to another column. Empty cells stay empty
+pub fn process_csv_encrypt(
+    input: &str,
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    columns: &[String],
+    key: &str,
+    writer_opts: &CsvWriterOpts,
+) -> Result<()> {
+    let encryptor = ChaCha20Poly1305Encryptor::load(key)?;
+    map_columns(
+        input,
+        output,
+        format,
+        opts,
+        columns,
+        writer_opts,
+        |column, value| encrypt_cell(&encryptor, column, value),
+    )
+}
+
+/// Decrypt the tokens written by `process_csv_encrypt`
+pub fn process_csv_decrypt(
+    input: &str,
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    columns: &[String],
+    key: &str,
+    writer_opts: &CsvWriterOpts,
+) -> Result<()> {
+    let decryptor = ChaCha20Poly1305Decryptor::load(key)?;
+    map_columns(
+        input,
+        output,
+        format,
+        opts,
+        columns,
+        writer_opts,
+        |column, value| decrypt_cell(&decryptor, column, value),
+    )
+}
+
+/// Encrypt a cell with its column name as associated data
+pub fn encrypt_cell(
+    encryptor: &ChaCha20Poly1305Encryptor,
+    column: &str,
+    value: &str,
+) -> Result<String> {
+    if value.is_empty() {
+        return Ok(String::new());
+    }
+    let encrypted = encryptor.encrypt_with_aad(value.as_bytes(), column.as_bytes())?;
+    Ok(BASE64_URL_SAFE_NO_PAD.encode(encrypted))
+}
+
+/// Decrypt a cell of the column it was encrypted in
+pub fn decrypt_cell(
+    decryptor: &ChaCha20Poly1305Decryptor,
+    column: &str,
+    token: &str,
+) -> Result<String> {
+    if token.is_empty() {
+        return Ok(String::new());
+    }
+    let encrypted = BASE64_URL_SAFE_NO_PAD.decode(token)?;
+    let decrypted = decryptor.decrypt_with_aad(&encrypted, column.as_bytes())?;
+    Ok(String::from_utf8(decrypted)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const KEY: &str = "fixtures/chacha20poly1305.key";
+
+    #[test]
+    fn test_cell_round_trip() -> Result<()> {
+        let encryptor = ChaCha20Poly1305Encryptor::load(KEY)?;
+        let decryptor = ChaCha20Poly1305Decryptor::load(KEY)?;
+        let a = encrypt_cell(&encryptor, "DOB", "Jan 28, 1978 (41)")?;
+        let b = encrypt_cell(&encryptor, "DOB", "Jan 28, 1978 (41)")?;
+        assert_ne!(a, b);
+        assert_eq!(decrypt_cell(&decryptor, "DOB", &a)?, "Jan 28, 1978 (41)");
+        assert_eq!(decrypt_cell(&decryptor, "DOB", &b)?, "Jan 28, 1978 (41)");
+        // a token only decrypts in the column it was made for
+        assert!(decrypt_cell(&decryptor, "Name", &a).is_err());
+        assert_eq!(encrypt_cell(&encryptor, "DOB", "")?, "");
+        assert!(decrypt_cell(&decryptor, "DOB", "abc").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_process_csv_encrypt() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let encrypted = dir.path().join("encrypted.csv");
+        let encrypted = encrypted.to_str().unwrap();
+        let decrypted = dir.path().join("decrypted.csv");
+        let decrypted = decrypted.to_str().unwrap();
+        let input = "assets/juventus.csv";
+        let columns = ["DOB".to_string()];
+        let (opts, writer_opts) = (Default::default(), Default::default());
+        process_csv_encrypt(
+            input,
+            encrypted,
+            OutputFormat::Csv,
+            &opts,
+            &columns,
+            KEY,
+            &writer_opts,
+        )?;
+
+        // every line is the input line with the quoted date replaced by its token
+        let original = fs::read_to_string(input)?;
+        let content = fs::read_to_string(encrypted)?;
+        let mut reader = csv::Reader::from_path(encrypted)?;
+        let tokens = reader
+            .records()
+            .map(|r| Ok(r?[2].to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        let mut reader = csv::Reader::from_path(input)?;
+        let dates = reader
+            .records()
+            .map(|r| Ok(r?[2].to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        let mut lines = content.lines();
+        let mut original_lines = original.lines();
+        assert_eq!(lines.next(), original_lines.next());
+        for ((line, original), (date, token)) in
+            lines.zip(original_lines).zip(dates.iter().zip(&tokens))
+        {
+            assert_ne!(date, token);
+            assert_eq!(line, original.replacen(&format!("\"{}\"", date), token, 1));
+        }
+        assert_eq!(tokens.len(), 27);
+
+        process_csv_decrypt(
+            encrypted,
+            decrypted,
+            OutputFormat::Csv,
+            &opts,
+            &columns,
+            KEY,
+            &writer_opts,
+        )?;
+        assert_eq!(fs::read_to_string(decrypted)?, original);
+        Ok(())
+    }
+}
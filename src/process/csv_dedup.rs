@@ -0,0 +1,90 @@
+use anyhow::Result;
+use std::collections::HashSet;
+
+use crate::{
+    column_index, csv_headers, csv_input, output_writer, record_to_value, string_values,
+    CsvReaderOpts, CsvWriterOpts, OutputFormat,
+};
+
+/// Drop the records whose key columns were already seen, the first one is kept.
+/// Without key columns whole records are compared
+pub fn process_csv_dedup(
+    input: &str,
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    keys: Option<&[String]>,
+    writer_opts: &CsvWriterOpts,
+) -> Result<()> {
+    let mut reader = csv_input(input, opts)?;
+    let headers = csv_headers(&mut reader, opts)?;
+    let columns = keys
+        .map(|keys| {
+            keys.iter()
+                .map(|k| column_index(&headers, k))
+                .collect::<Result<Vec<_>>>()
+        })
+        .transpose()?;
+
+    let mut seen = HashSet::new();
+    let mut writer = output_writer(output, format, writer_opts)?;
+    writer.write_headers(&headers)?;
+    for record in reader.records() {
+        let record = record?;
+        let key = match &columns {
+            Some(columns) => columns
+                .iter()
+                .map(|i| record.get(*i).unwrap_or_default().to_string())
+                .collect::<Vec<_>>(),
+            None => record.iter().map(|f| f.to_string()).collect(),
+        };
+        if seen.insert(key) {
+            writer.write_record(&record_to_value(&headers, string_values(&record)))?;
+        }
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_dedup() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("players.csv");
+        fs::write(
+            &input,
+            "name,position\nBuffon,Goalkeeper\nPerin,Goalkeeper\nBuffon,Goalkeeper\n",
+        )?;
+        let input = input.to_str().unwrap();
+        let output = dir.path().join("out.csv");
+        let output = output.to_str().unwrap();
+        let dedup = |keys: Option<&[String]>| -> Result<String> {
+            let opts = CsvReaderOpts::default();
+            process_csv_dedup(
+                input,
+                output,
+                OutputFormat::Csv,
+                &opts,
+                keys,
+                &Default::default(),
+            )?;
+            Ok(fs::read_to_string(output)?)
+        };
+        assert_eq!(
+            dedup(None)?,
+            "name,position\nBuffon,Goalkeeper\nPerin,Goalkeeper\n"
+        );
+        assert_eq!(
+            dedup(Some(&["position".to_string()]))?,
+            "name,position\nBuffon,Goalkeeper\n"
+        );
+        assert!(dedup(Some(&["kit".to_string()])).is_err());
+
+        fs::write(dir.path().join("players.csv"), "name,position\n")?;
+        assert_eq!(dedup(None)?, "name,position\n");
+        Ok(())
+    }
+}
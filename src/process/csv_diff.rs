@@ -0,0 +1,284 @@
+use anyhow::Result;
+use colored::Colorize;
+use serde_json::{json, Map, Value};
+use std::{
+    collections::HashMap,
+    io::{IsTerminal, Write},
+};
+
+use crate::{
+    cell_to_string, csv_records, get_data_writer, CsvReaderOpts, CsvTypeOpts, ReportFormat,
+};
+
+/// Changed cells of a row found in both files
+pub struct RowChange {
+    pub key: Value,
+    pub changes: Map<String, Value>,
+}
+
+/// Differences between two CSV files, rows are matched by their key columns
+#[derive(Default)]
+pub struct CsvDiff {
+    pub columns_added: Vec<String>,
+    pub columns_removed: Vec<String>,
+    pub added: Vec<Value>,
+    pub removed: Vec<Value>,
+    pub modified: Vec<RowChange>,
+    pub unchanged: usize,
+}
+
+/// Compare two CSV files and write the differences as a report or JSON
+pub fn process_csv_diff(
+    old: &str,
+    new: &str,
+    output: &str,
+    format: ReportFormat,
+    opts: &CsvReaderOpts,
+    keys: &[String],
+) -> Result<()> {
+    let diff = diff_csv(old, new, opts, keys)?;
+    let mut writer = get_data_writer(output)?;
+    match format {
+        ReportFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &diff.to_value())?;
+            writeln!(writer)?;
+        }
+        ReportFormat::Text => {
+            let color = output == "-" && std::io::stdout().is_terminal();
+            colored::control::set_override(color);
+            diff.write_report(&mut writer, old, new)?;
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Compare the rows of two CSV files. The old file is held in memory, the new one
+/// is streamed
+pub fn diff_csv(old: &str, new: &str, opts: &CsvReaderOpts, keys: &[String]) -> Result<CsvDiff> {
+    let type_opts = CsvTypeOpts::default();
+    let (old_headers, old_records) = csv_records(old, opts, &type_opts, 0)?;
+    let (new_headers, new_records) = csv_records(new, opts, &type_opts, 0)?;
+    for key in keys {
+        if !old_headers.contains(key) || !new_headers.contains(key) {
+            anyhow::bail!("Key column {:?} must be in both files", key);
+        }
+    }
+
+    let mut old_rows = Vec::new();
+    let mut index = HashMap::new();
+    for record in old_records {
+        let record = record?;
+        let key = row_key(&record, keys);
+        if index.insert(key.to_string(), old_rows.len()).is_some() {
+            anyhow::bail!("Duplicate key {} in {}", key, old);
+        }
+        old_rows.push(Some(record));
+    }
+
+    let mut diff = CsvDiff {
+        columns_added: new_headers
+            .iter()
+            .filter(|h| !old_headers.contains(h))
+            .cloned()
+            .collect(),
+        columns_removed: old_headers
+            .iter()
+            .filter(|h| !new_headers.contains(h))
+            .cloned()
+            .collect(),
+        ..Default::default()
+    };
+    // added and removed columns are reported once, rows are compared on the shared ones
+    let columns = old_headers
+        .iter()
+        .filter(|h| new_headers.contains(h))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let mut seen = HashMap::new();
+    for record in new_records {
+        let record = record?;
+        let key = row_key(&record, keys);
+        if seen.insert(key.to_string(), ()).is_some() {
+            anyhow::bail!("Duplicate key {} in {}", key, new);
+        }
+        let Some(old_record) = index
+            .get(&key.to_string())
+            .and_then(|i| old_rows[*i].take())
+        else {
+            diff.added.push(record);
+            continue;
+        };
+        let changes = columns
+            .iter()
+            .filter_map(|column| {
+                let before = old_record.get(column).unwrap_or(&Value::Null);
+                let after = record.get(column).unwrap_or(&Value::Null);
+                (before != after)
+                    .then(|| (column.clone(), json!({"before": before, "after": after})))
+            })
+            .collect::<Map<_, _>>();
+        if changes.is_empty() {
+            diff.unchanged += 1;
+        } else {
+            diff.modified.push(RowChange { key, changes });
+        }
+    }
+    diff.removed = old_rows.into_iter().flatten().collect();
+    Ok(diff)
+}
+
+/// Values of the key columns of a record
+fn row_key(record: &Value, keys: &[String]) -> Value {
+    let key = keys
+        .iter()
+        .map(|k| (k.clone(), record.get(k).cloned().unwrap_or(Value::Null)))
+        .collect::<Map<_, _>>();
+    Value::Object(key)
+}
+
+impl CsvDiff {
+    pub fn is_empty(&self) -> bool {
+        self.columns_added.is_empty()
+            && self.columns_removed.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.modified.is_empty()
+    }
+
+    pub fn to_value(&self) -> Value {
+        let modified = self
+            .modified
+            .iter()
+            .map(|m| json!({"key": m.key, "changes": m.changes}))
+            .collect::<Vec<_>>();
+        json!({
+            "columns": {
+                "added": self.columns_added,
+                "removed": self.columns_removed,
+            },
+            "added": self.added,
+            "removed": self.removed,
+            "modified": modified,
+            "summary": {
+                "added": self.added.len(),
+                "removed": self.removed.len(),
+                "modified": self.modified.len(),
+                "unchanged": self.unchanged,
+            },
+        })
+    }
+
+    /// Write a report in the style of a unified diff, one line per row
+    pub fn write_report<W: Write>(&self, writer: &mut W, old: &str, new: &str) -> Result<()> {
+        writeln!(writer, "{}", format!("--- {}", old).bold())?;
+        writeln!(writer, "{}", format!("+++ {}", new).bold())?;
+        if !self.columns_added.is_empty() {
+            let columns = format!("+ columns: {}", self.columns_added.join(", "));
+            writeln!(writer, "{}", columns.green())?;
+        }
+        if !self.columns_removed.is_empty() {
+            let columns = format!("- columns: {}", self.columns_removed.join(", "));
+            writeln!(writer, "{}", columns.red())?;
+        }
+        for record in &self.removed {
+            writeln!(writer, "{}", format!("- {}", describe(record)).red())?;
+        }
+        for record in &self.added {
+            writeln!(writer, "{}", format!("+ {}", describe(record)).green())?;
+        }
+        for row in &self.modified {
+            writeln!(writer, "{}", format!("~ {}", describe(&row.key)).yellow())?;
+            for (column, change) in &row.changes {
+                writeln!(
+                    writer,
+                    "    {}: {} -> {}",
+                    column,
+                    cell(&change["before"]).red(),
+                    cell(&change["after"]).green()
+                )?;
+            }
+        }
+        writeln!(
+            writer,
+            "{} added, {} removed, {} modified, {} unchanged",
+            self.added.len(),
+            self.removed.len(),
+            self.modified.len(),
+            self.unchanged
+        )?;
+        Ok(())
+    }
+}
+
+/// `Name=Perin, Kit Number=37`
+fn describe(record: &Value) -> String {
+    record
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(k, v)| format!("{}={}", k, cell(v)))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Cell shown in the report, quoted so empty and missing values stand out
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => "(missing)".to_string(),
+        v => format!("{:?}", cell_to_string(v)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_diff_csv() -> Result<()> {
+        let mut new = tempfile::NamedTempFile::new()?;
+        let content = std::fs::read_to_string("assets/juventus.csv")?
+            .replace("Mattia Perin,Goalkeeper", "Mattia Perin,Striker")
+            .replace(
+                "Gianluigi Buffon,Goalkeeper,\"Jan 28, 1978 (41)\",Italy,77\n",
+                "",
+            )
+            + "Moise Kean,Centre-Forward,\"Feb 28, 2000 (19)\",Italy,18\n";
+        new.write_all(content.as_bytes())?;
+        let new = new.path().to_str().unwrap();
+
+        let keys = vec!["Name".to_string()];
+        let diff = diff_csv("assets/juventus.csv", new, &Default::default(), &keys)?;
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.removed[0]["Name"], "Gianluigi Buffon");
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(
+            Value::Object(diff.modified[0].changes.clone()),
+            json!({"Position": {"before": "Goalkeeper", "after": "Striker"}})
+        );
+        assert_eq!(diff.unchanged, 25);
+
+        let same = diff_csv(
+            "assets/juventus.csv",
+            "assets/juventus.csv",
+            &Default::default(),
+            &keys,
+        )?;
+        assert!(same.is_empty());
+
+        let mut renamed = tempfile::NamedTempFile::new()?;
+        let content = std::fs::read_to_string("assets/juventus.csv")?
+            .replacen("Kit Number", "Shirt", 1)
+            .replace("Mattia Perin,Goalkeeper", "Mattia Perin,Striker");
+        renamed.write_all(content.as_bytes())?;
+        let renamed = renamed.path().to_str().unwrap();
+        let diff = diff_csv("assets/juventus.csv", renamed, &Default::default(), &keys)?;
+        assert_eq!(diff.columns_added, ["Shirt"]);
+        assert_eq!(diff.columns_removed, ["Kit Number"]);
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(diff.unchanged, 26);
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::Result;
+use encoding_rs::{Decoder, DecoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+
+use crate::{get_data_reader, CsvEncoding};
+
+/// Bytes looked at to detect the encoding
+const SNIFF_BYTES: u64 = 64 * 1024;
+
+/// Decode the input to UTF-8 and strip the byte order mark. A given encoding
+/// replaces bytes it cannot decode, a detected one is only a guess from the
+/// start of the input, so any byte it cannot decode later on is an error
+pub fn decode_reader<'a, R: Read + 'a>(
+    mut reader: R,
+    encoding: CsvEncoding,
+) -> Result<Box<dyn Read + 'a>> {
+    match encoding {
+        CsvEncoding::Label(encoding) => {
+            // a BOM wins over the given encoding, so UTF-16 files are read right in any mode
+            let reader = DecodeReaderBytesBuilder::new()
+                .encoding(Some(encoding))
+                .bom_override(true)
+                .strip_bom(true)
+                .build(reader);
+            Ok(Box::new(reader))
+        }
+        CsvEncoding::Auto => {
+            let sample = sniff(&mut reader)?;
+            let encoding = detect_encoding(&sample);
+            let reader = BufReader::new(Cursor::new(sample).chain(reader));
+            Ok(Box::new(StrictDecoder::new(reader, encoding)))
+        }
+    }
+}
+
+/// The encoding `--encoding auto` picks for an input file, "-" for stdin
+pub fn input_encoding(input: &str) -> Result<&'static Encoding> {
+    let sample = sniff(&mut get_data_reader(input)?)?;
+    Ok(detect_encoding(&sample))
+}
+
+/// Read the start of the input the encoding is detected from
+fn sniff<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut sample = Vec::new();
+    reader.take(SNIFF_BYTES).read_to_end(&mut sample)?;
+    Ok(sample)
+}
+
+/// Guess the encoding from the start of the input: a BOM, valid UTF-8, UTF-16
+/// without BOM from its zero bytes, otherwise Windows-1252
+pub fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(_) => return UTF_8,
+        // the sample may end in the middle of a character
+        Err(e) if e.error_len().is_none() => return UTF_8,
+        Err(_) => {}
+    }
+    let pairs = bytes.len() / 2;
+    let zeros_at = |offset: usize| {
+        bytes
+            .iter()
+            .skip(offset)
+            .step_by(2)
+            .filter(|b| **b == 0)
+            .count()
+    };
+    if pairs > 0 && zeros_at(1) * 2 > pairs {
+        UTF_16LE
+    } else if pairs > 0 && zeros_at(0) * 2 > pairs {
+        UTF_16BE
+    } else {
+        WINDOWS_1252
+    }
+}
+
+/// Decode to UTF-8 without replacement characters, failing on the first
+/// malformed sequence with its byte offset
+struct StrictDecoder<R> {
+    reader: R,
+    decoder: Decoder,
+    encoding: &'static Encoding,
+    // decoded bytes not read yet
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+    offset: u64,
+    done: bool,
+}
+
+impl<R: BufRead> StrictDecoder<R> {
+    fn new(reader: R, encoding: &'static Encoding) -> Self {
+        Self {
+            reader,
+            decoder: encoding.new_decoder_with_bom_removal(),
+            encoding,
+            buf: vec![0; 8 * 1024],
+            pos: 0,
+            len: 0,
+            offset: 0,
+            done: false,
+        }
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        let input = self.reader.fill_buf()?;
+        let last = input.is_empty();
+        let (result, read, written) =
+            self.decoder
+                .decode_to_utf8_without_replacement(input, &mut self.buf, last);
+        self.reader.consume(read);
+        self.offset += read as u64;
+        self.pos = 0;
+        self.len = written;
+        match result {
+            DecoderResult::Malformed(bad, after) => {
+                let at = self.offset - bad as u64 - after as u64;
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Input is not valid {} at byte {}, the encoding was detected from the \
+                         first {} KiB, set it with --encoding",
+                        self.encoding.name(),
+                        at,
+                        SNIFF_BYTES / 1024
+                    ),
+                ))
+            }
+            DecoderResult::InputEmpty if last => {
+                self.done = true;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<R: BufRead> Read for StrictDecoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.len && !self.done {
+            self.fill()?;
+        }
+        let n = out.len().min(self.len - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &[u8], encoding: CsvEncoding) -> Result<String> {
+        let mut content = String::new();
+        decode_reader(bytes, encoding)?.read_to_string(&mut content)?;
+        Ok(content)
+    }
+
+    #[test]
+    fn test_decode_reader() -> Result<()> {
+        // "Name,Città\nMüller,Torino\n" in Windows-1252
+        let latin = b"Name,Citt\xe0\nM\xfcller,Torino\n";
+        assert_eq!(detect_encoding(latin), WINDOWS_1252);
+        assert_eq!(
+            decode(latin, CsvEncoding::Auto)?,
+            "Name,Città\nMüller,Torino\n"
+        );
+
+        let mut utf16 = vec![0xff, 0xfe];
+        utf16.extend("a,é\n".encode_utf16().flat_map(|c| c.to_le_bytes()));
+        assert_eq!(decode(&utf16, CsvEncoding::Auto)?, "a,é\n");
+        assert_eq!(detect_encoding(&utf16[2..]), UTF_16LE);
+
+        assert_eq!(
+            decode("\u{feff}a,b\n".as_bytes(), CsvEncoding::Auto)?,
+            "a,b\n"
+        );
+        let latin1 = "latin1".parse::<CsvEncoding>().unwrap();
+        assert_eq!(decode(b"caf\xe9", latin1)?, "café");
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_reader_past_sniff() -> Result<()> {
+        // ASCII up to the sniff limit, then a Windows-1252 byte
+        let mut bytes = "a,b\n".repeat(20_000).into_bytes();
+        bytes.extend(b"caf\xe9,1\n");
+        let err = decode(&bytes, CsvEncoding::Auto).unwrap_err();
+        assert!(err.to_string().contains("byte 80003"), "{}", err);
+
+        let latin1 = "windows-1252".parse::<CsvEncoding>().unwrap();
+        assert!(decode(&bytes, latin1)?.ends_with("café,1\n"));
+        let mut utf8 = "a,b\n".repeat(20_000).into_bytes();
+        utf8.extend("café,1\n".as_bytes());
+        assert!(decode(&utf8, CsvEncoding::Auto)?.ends_with("café,1\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,396 @@
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Days, NaiveDate};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::time::SystemTime;
+
+use crate::{output_writer, ColumnSchema, ColumnType, CsvSchema, CsvWriterOpts, OutputFormat};
+
+/// Share of empty cells in columns that are not required
+const NULL_RATE: f64 = 0.05;
+/// Date ages are counted up to when a seed is given without `as_of`, so the
+/// same seed gives the same records on any day
+const SEEDED_AS_OF: NaiveDate = NaiveDate::from_ymd_opt(2019, 6, 30).unwrap();
+
+const FIRST_NAMES: &[&str] = &[
+    "Alessandro",
+    "Andrea",
+    "Antonio",
+    "Carlo",
+    "Daniele",
+    "Emre",
+    "Federico",
+    "Gianluigi",
+    "Giorgio",
+    "Gonzalo",
+    "Juan",
+    "Leonardo",
+    "Luca",
+    "Marco",
+    "Mario",
+    "Mattia",
+    "Miralem",
+    "Moise",
+    "Paulo",
+    "Rodrigo",
+    "Sami",
+    "Wojciech",
+    "Blaise",
+    "Cristiano",
+    "Douglas",
+    "Joao",
+    "Kevin",
+    "Lukas",
+    "Matthijs",
+    "Nicolo",
+    "Pierre",
+    "Samuel",
+    "Thiago",
+    "Weston",
+];
+
+const LAST_NAMES: &[&str] = &[
+    "Alex Sandro",
+    "Bentancur",
+    "Bernardeschi",
+    "Bonucci",
+    "Buffon",
+    "Can",
+    "Cancelo",
+    "Chiellini",
+    "Costa",
+    "Cuadrado",
+    "De Sciglio",
+    "Dybala",
+    "Higuain",
+    "Kean",
+    "Khedira",
+    "Mandzukic",
+    "Matuidi",
+    "Perin",
+    "Pinsoglio",
+    "Pjanic",
+    "Rugani",
+    "Spinazzola",
+    "Szczesny",
+    "Barzagli",
+    "Caceres",
+    "Emerson",
+    "Locatelli",
+    "McKennie",
+    "Rabiot",
+    "Vlahovic",
+    "Chiesa",
+    "Danilo",
+    "Gatti",
+    "Kostic",
+];
+
+const COUNTRIES: &[&str] = &[
+    "Argentina",
+    "Belgium",
+    "Bosnia-Herzegovina",
+    "Brazil",
+    "Colombia",
+    "Croatia",
+    "Denmark",
+    "England",
+    "France",
+    "Germany",
+    "Italy",
+    "Mexico",
+    "Netherlands",
+    "Nigeria",
+    "Poland",
+    "Portugal",
+    "Serbia",
+    "Spain",
+    "Turkey",
+    "United States",
+    "Uruguay",
+    "Wales",
+];
+
+const WORDS: &[&str] = &[
+    "alpha", "bravo", "charlie", "delta", "echo", "foxtrot", "golf", "hotel", "india", "juliet",
+    "kilo", "lima", "mike", "november", "oscar", "papa", "quebec", "romeo", "sierra", "tango",
+];
+
+const EMAIL_DOMAINS: &[&str] = &["example.com", "example.org", "example.net"];
+
+/// Generators that can be set with `fake:` on a schema column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FakeKind {
+    /// First and last name
+    Name,
+    FirstName,
+    LastName,
+    Email,
+    /// Random (version 4) UUID
+    Uuid,
+    /// Date of birth with the age, e.g. "Apr 18, 1990 (29)"
+    Dob,
+    Country,
+    Word,
+}
+
+/// Generator of the values of one column
+#[derive(Debug, Clone)]
+enum Generator {
+    Fake(FakeKind),
+    Choice(Vec<String>),
+    Int(i64, i64),
+    Float(f64, f64),
+    Bool,
+    /// Date in the last 50 years, written with the chrono format
+    Date(String),
+}
+
+/// Write `rows` records shaped like the schema. Names, emails, UUIDs, dates of
+/// birth and countries are guessed from the column names unless the columns set
+/// `fake:`, enums pick one of their values and numbers stay within min and max.
+/// Columns with a pattern need `fake:`, as no generator follows a regex. The same
+/// seed and date always give the same records
+pub fn process_csv_fake(
+    schema: &str,
+    output: &str,
+    format: OutputFormat,
+    rows: usize,
+    seed: Option<u64>,
+    as_of: Option<NaiveDate>,
+    writer_opts: &CsvWriterOpts,
+) -> Result<()> {
+    let schema = CsvSchema::load(schema)?;
+    let mut rng = match seed {
+        Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+        None => ChaCha8Rng::from_entropy(),
+    };
+    let as_of = match (as_of, seed) {
+        (Some(date), _) => date,
+        (None, Some(_)) => SEEDED_AS_OF,
+        (None, None) => today()?,
+    };
+    let mut writer = output_writer(output, format, writer_opts)?;
+    for record in fake_records(&schema, &mut rng, as_of)?.take(rows) {
+        writer.write_record(&record)?;
+    }
+    writer.finish()
+}
+
+/// Endless records for the schema, `as_of` is the date ages are counted up to
+pub fn fake_records<'a, R: Rng>(
+    schema: &'a CsvSchema,
+    rng: &'a mut R,
+    as_of: NaiveDate,
+) -> Result<impl Iterator<Item = Value> + 'a> {
+    let generators = schema
+        .columns
+        .iter()
+        .map(|column| column_generator(column, &schema.date_formats))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(std::iter::repeat_with(move || {
+        let record = schema
+            .columns
+            .iter()
+            .zip(&generators)
+            .map(|(column, generator)| {
+                let value = if !column.required && rng.gen_bool(NULL_RATE) {
+                    Value::Null
+                } else {
+                    generator.generate(rng, as_of)
+                };
+                (column.name.clone(), value)
+            })
+            .collect::<Map<_, _>>();
+        Value::Object(record)
+    }))
+}
+
+fn column_generator(column: &ColumnSchema, date_formats: &[String]) -> Result<Generator> {
+    if let Some(kind) = column.fake {
+        return Ok(Generator::Fake(kind));
+    }
+    anyhow::ensure!(
+        column.pattern.is_none(),
+        "Column {} has a pattern, set `fake:` to the kind of values that match it",
+        column.name
+    );
+    if let Some(values) = &column.values {
+        anyhow::ensure!(
+            !values.is_empty(),
+            "Column {} has an empty enum",
+            column.name
+        );
+        return Ok(Generator::Choice(values.clone()));
+    }
+    let bound = |n: &Option<serde_json::Number>| n.as_ref().and_then(|n| n.as_f64());
+    let (min, max) = (bound(&column.min), bound(&column.max));
+    let generator = match column.column_type {
+        ColumnType::Int => {
+            let min = min.map_or(0, |n| n.ceil() as i64);
+            let max = max.map_or(min.saturating_add(1000), |n| n.floor() as i64);
+            anyhow::ensure!(min <= max, "Column {} has min above max", column.name);
+            Generator::Int(min, max)
+        }
+        ColumnType::Float => {
+            let min = min.unwrap_or(0.0);
+            let max = max.unwrap_or(min + 1000.0);
+            anyhow::ensure!(min <= max, "Column {} has min above max", column.name);
+            Generator::Float(min, max)
+        }
+        ColumnType::Bool => Generator::Bool,
+        ColumnType::Date => Generator::Date(
+            date_formats
+                .first()
+                .cloned()
+                .unwrap_or_else(|| "%Y-%m-%d".to_string()),
+        ),
+        ColumnType::Null | ColumnType::String => Generator::Fake(guess_kind(&column.name)),
+    };
+    Ok(generator)
+}
+
+/// Pick a generator for a text column from its name
+fn guess_kind(name: &str) -> FakeKind {
+    let name = name.to_lowercase();
+    let has = |s: &str| name.contains(s);
+    if has("mail") {
+        FakeKind::Email
+    } else if has("uuid") || has("guid") {
+        FakeKind::Uuid
+    } else if name == "dob" || has("birth") {
+        FakeKind::Dob
+    } else if has("first") && has("name") {
+        FakeKind::FirstName
+    } else if (has("last") || has("sur")) && has("name") {
+        FakeKind::LastName
+    } else if has("name") {
+        FakeKind::Name
+    } else if has("country") || has("nationality") {
+        FakeKind::Country
+    } else {
+        FakeKind::Word
+    }
+}
+
+impl Generator {
+    fn generate<R: Rng>(&self, rng: &mut R, as_of: NaiveDate) -> Value {
+        match self {
+            Generator::Fake(FakeKind::Name) => {
+                let first = pick(rng, FIRST_NAMES);
+                format!("{} {}", first, pick(rng, LAST_NAMES)).into()
+            }
+            Generator::Fake(FakeKind::FirstName) => pick(rng, FIRST_NAMES).into(),
+            Generator::Fake(FakeKind::LastName) => pick(rng, LAST_NAMES).into(),
+            Generator::Fake(FakeKind::Email) => {
+                let first = pick(rng, FIRST_NAMES).to_lowercase();
+                let last = pick(rng, LAST_NAMES).to_lowercase().replace(' ', "");
+                let n = rng.gen_range(1..100);
+                let domain = pick(rng, EMAIL_DOMAINS);
+                format!("{}.{}{}@{}", first, last, n, domain).into()
+            }
+            Generator::Fake(FakeKind::Uuid) => {
+                let mut bytes = rng.gen::<[u8; 16]>();
+                bytes[6] = (bytes[6] & 0x0f) | 0x40;
+                bytes[8] = (bytes[8] & 0x3f) | 0x80;
+                let hex = bytes
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<String>();
+                format!(
+                    "{}-{}-{}-{}-{}",
+                    &hex[..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..]
+                )
+                .into()
+            }
+            Generator::Fake(FakeKind::Dob) => {
+                let born = random_date(rng, as_of, 16 * 366, 40 * 365);
+                let mut age = as_of.year() - born.year();
+                if (as_of.month(), as_of.day()) < (born.month(), born.day()) {
+                    age -= 1;
+                }
+                format!("{} ({})", born.format("%b %-d, %Y"), age).into()
+            }
+            Generator::Fake(FakeKind::Country) => pick(rng, COUNTRIES).into(),
+            Generator::Fake(FakeKind::Word) => pick(rng, WORDS).into(),
+            Generator::Choice(values) => values.choose(rng).cloned().unwrap_or_default().into(),
+            Generator::Int(min, max) => rng.gen_range(*min..=*max).into(),
+            Generator::Float(min, max) => {
+                let value = rng.gen_range(*min..=*max);
+                ((value * 100.0).round() / 100.0).clamp(*min, *max).into()
+            }
+            Generator::Bool => rng.gen_bool(0.5).into(),
+            Generator::Date(format) => random_date(rng, as_of, 0, 50 * 365)
+                .format(format)
+                .to_string()
+                .into(),
+        }
+    }
+}
+
+fn pick<R: Rng>(rng: &mut R, list: &[&'static str]) -> &'static str {
+    list.choose(rng).copied().unwrap_or_default()
+}
+
+/// A date between `min_days` and `max_days` before `as_of`
+fn random_date<R: Rng>(rng: &mut R, as_of: NaiveDate, min_days: u64, max_days: u64) -> NaiveDate {
+    let days = rng.gen_range(min_days..=max_days);
+    as_of.checked_sub_days(Days::new(days)).unwrap_or(as_of)
+}
+
+fn today() -> Result<NaiveDate> {
+    let secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs();
+    let now = DateTime::from_timestamp(secs as i64, 0)
+        .ok_or_else(|| anyhow::anyhow!("Invalid system time"))?;
+    Ok(now.date_naive())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{serialize_records, validate_csv};
+    use std::fs;
+
+    #[test]
+    fn test_fake_records_match_schema() -> Result<()> {
+        let schema = CsvSchema::load("assets/juventus.schema.yaml")?;
+        let as_of = NaiveDate::from_ymd_opt(2019, 6, 30).unwrap();
+        let records = |seed| -> Result<Vec<Value>> {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed);
+            let records = fake_records(&schema, &mut rng, as_of)?.take(200).collect();
+            Ok(records)
+        };
+        let first = records(7)?;
+        assert_eq!(first, records(7)?);
+        assert_ne!(first, records(8)?);
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("players.csv");
+        let content = serialize_records(&first, OutputFormat::Csv, &Default::default())?;
+        fs::write(&path, content)?;
+        let (rows, violations) =
+            validate_csv(path.to_str().unwrap(), &schema, &Default::default())?;
+        assert_eq!(rows, 200);
+        assert!(violations.is_empty(), "{:?}", violations);
+
+        let mut schema = schema;
+        schema.columns[2].fake = None;
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        let err = fake_records(&schema, &mut rng, as_of).err().unwrap();
+        assert!(
+            err.to_string().contains("Column DOB has a pattern"),
+            "{}",
+            err
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,508 @@
+use anyhow::Result;
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::{
+    cmp::Ordering, collections::HashSet, fmt::Display, iter::Peekable, str::CharIndices,
+    str::FromStr,
+};
+
+use crate::CsvFilterOpts;
+
+/// Filter expression used by `--where`
+///
+/// ```text
+/// expr    := and ("||" and)*
+/// and     := unary ("&&" unary)*
+/// unary   := "!" unary | "(" expr ")" | operand (op operand)?
+/// op      := "==" | "!=" | "<" | "<=" | ">" | ">=" | "=~" | "!~"
+/// operand := column | `quoted column` | "string" | 'string' | number | true | false | null
+/// ```
+#[derive(Debug, Clone)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, CmpOp, Operand),
+    Match(Operand, Regex, bool),
+    Truthy(Operand),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Column(String),
+    Literal(Value),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    Cmp(CmpOp),
+    Match(bool),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+impl Expr {
+    /// Evaluate the expression against a record
+    pub fn eval(&self, record: &Value) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(record) && b.eval(record),
+            Expr::Or(a, b) => a.eval(record) || b.eval(record),
+            Expr::Not(e) => !e.eval(record),
+            Expr::Compare(a, op, b) => compare(a.resolve(record), *op, b.resolve(record)),
+            Expr::Match(a, re, negate) => re.is_match(&to_text(a.resolve(record))) != *negate,
+            Expr::Truthy(a) => match a.resolve(record) {
+                Value::Null => false,
+                Value::Bool(b) => *b,
+                Value::Number(n) => n.as_f64() != Some(0.0),
+                Value::String(s) => !s.is_empty() && s != "false",
+                _ => true,
+            },
+        }
+    }
+
+    /// Columns referenced by the expression
+    pub fn columns(&self) -> Vec<&str> {
+        let mut columns = Vec::new();
+        self.collect_columns(&mut columns);
+        columns
+    }
+
+    fn collect_columns<'a>(&'a self, columns: &mut Vec<&'a str>) {
+        let mut push = |operand: &'a Operand| {
+            if let Operand::Column(name) = operand {
+                columns.push(name.as_str());
+            }
+        };
+        match self {
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                a.collect_columns(columns);
+                b.collect_columns(columns);
+            }
+            Expr::Not(e) => e.collect_columns(columns),
+            Expr::Compare(a, _, b) => {
+                push(a);
+                push(b);
+            }
+            Expr::Match(a, _, _) | Expr::Truthy(a) => push(a),
+        }
+    }
+}
+
+impl Operand {
+    fn resolve<'a>(&'a self, record: &'a Value) -> &'a Value {
+        match self {
+            Operand::Column(name) => record.get(name).unwrap_or(&Value::Null),
+            Operand::Literal(value) => value,
+        }
+    }
+}
+
+/// Numbers, or strings holding numbers, are compared numerically, anything else as text.
+/// Null equals null and empty strings, and is never ordered
+fn compare(a: &Value, op: CmpOp, b: &Value) -> bool {
+    let is_null = |v: &Value| v.is_null() || v.as_str() == Some("");
+    if a.is_null() || b.is_null() {
+        let eq = is_null(a) && is_null(b);
+        return match op {
+            CmpOp::Eq => eq,
+            CmpOp::Ne => !eq,
+            _ => false,
+        };
+    }
+    let ordering = match (to_number(a), to_number(b)) {
+        (Some(x), Some(y)) => x.partial_cmp(&y),
+        _ => Some(to_text(a).cmp(&to_text(b))),
+    };
+    let Some(ordering) = ordering else {
+        return false;
+    };
+    match op {
+        CmpOp::Eq => ordering == Ordering::Equal,
+        CmpOp::Ne => ordering != Ordering::Equal,
+        CmpOp::Lt => ordering == Ordering::Less,
+        CmpOp::Le => ordering != Ordering::Greater,
+        CmpOp::Gt => ordering == Ordering::Greater,
+        CmpOp::Ge => ordering != Ordering::Less,
+    }
+}
+
+fn to_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok().filter(|n: &f64| n.is_finite()),
+        _ => None,
+    }
+}
+
+fn to_text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+        match parser.tokens.get(parser.pos) {
+            None => Ok(expr),
+            Some((token, at)) => Err(format!("unexpected {} at position {}", token, at)),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<(Token, usize)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some(&(at, c)) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '(' | ')' => {
+                chars.next();
+                if c == '(' {
+                    Token::LParen
+                } else {
+                    Token::RParen
+                }
+            }
+            '"' | '\'' | '`' => {
+                chars.next();
+                let text = read_quoted(&mut chars, c)
+                    .ok_or_else(|| format!("unterminated {} at position {}", c, at))?;
+                if c == '`' {
+                    Token::Ident(text)
+                } else {
+                    Token::Str(text)
+                }
+            }
+            '=' | '!' | '<' | '>' | '&' | '|' => read_operator(&mut chars)
+                .ok_or_else(|| format!("invalid operator at position {}", at))?,
+            c if c.is_ascii_digit() || c == '-' || c == '.' => {
+                let text = read_while(&mut chars, |c| {
+                    c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '+')
+                });
+                let num = text
+                    .parse()
+                    .map_err(|_| format!("invalid number {:?} at position {}", text, at))?;
+                Token::Num(num)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                Token::Ident(read_while(&mut chars, |c| c.is_alphanumeric() || c == '_'))
+            }
+            c => return Err(format!("unexpected character {:?} at position {}", c, at)),
+        };
+        tokens.push((token, at));
+    }
+    Ok(tokens)
+}
+
+fn read_quoted(chars: &mut Peekable<CharIndices>, quote: char) -> Option<String> {
+    let mut text = String::new();
+    while let Some((_, c)) = chars.next() {
+        match c {
+            '\\' => text.push(chars.next()?.1),
+            c if c == quote => return Some(text),
+            c => text.push(c),
+        }
+    }
+    None
+}
+
+fn read_operator(chars: &mut Peekable<CharIndices>) -> Option<Token> {
+    let (_, first) = chars.next()?;
+    let second = chars.peek().map(|&(_, c)| c);
+    let (token, double) = match (first, second) {
+        ('=', Some('=')) => (Token::Cmp(CmpOp::Eq), true),
+        ('=', Some('~')) => (Token::Match(false), true),
+        ('!', Some('=')) => (Token::Cmp(CmpOp::Ne), true),
+        ('!', Some('~')) => (Token::Match(true), true),
+        ('!', _) => (Token::Not, false),
+        ('<', Some('=')) => (Token::Cmp(CmpOp::Le), true),
+        ('<', _) => (Token::Cmp(CmpOp::Lt), false),
+        ('>', Some('=')) => (Token::Cmp(CmpOp::Ge), true),
+        ('>', _) => (Token::Cmp(CmpOp::Gt), false),
+        ('&', Some('&')) => (Token::And, true),
+        ('|', Some('|')) => (Token::Or, true),
+        _ => return None,
+    };
+    if double {
+        chars.next();
+    }
+    Some(token)
+}
+
+fn read_while(chars: &mut Peekable<CharIndices>, f: impl Fn(char) -> bool) -> String {
+    let mut text = String::new();
+    while let Some(&(_, c)) = chars.peek() {
+        if !f(c) {
+            break;
+        }
+        text.push(c);
+        chars.next();
+    }
+    text
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|(t, _)| t.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn error(&self, expected: &str) -> String {
+        match self.tokens.get(self.pos) {
+            Some((token, at)) => {
+                format!("expected {}, found {} at position {}", expected, token, at)
+            }
+            None => format!("expected {}, found end of expression", expected),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.next();
+                Ok(Expr::Not(Box::new(self.parse_unary()?)))
+            }
+            Some(Token::LParen) => {
+                self.next();
+                let expr = self.parse_or()?;
+                if self.peek() != Some(&Token::RParen) {
+                    return Err(self.error("')'"));
+                }
+                self.next();
+                Ok(expr)
+            }
+            _ => self.parse_comparison(),
+        }
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, String> {
+        let left = self.parse_operand()?;
+        match self.peek() {
+            Some(&Token::Cmp(op)) => {
+                self.next();
+                Ok(Expr::Compare(left, op, self.parse_operand()?))
+            }
+            Some(&Token::Match(negate)) => {
+                self.next();
+                let Some(Token::Str(pattern)) = self.peek().cloned() else {
+                    return Err(self.error("a quoted regex"));
+                };
+                self.next();
+                let re = Regex::new(&pattern).map_err(|e| format!("invalid regex: {}", e))?;
+                Ok(Expr::Match(left, re, negate))
+            }
+            _ => Ok(Expr::Truthy(left)),
+        }
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand, String> {
+        let operand = match self.peek() {
+            Some(Token::Ident(name)) => match name.as_str() {
+                "true" => Operand::Literal(Value::Bool(true)),
+                "false" => Operand::Literal(Value::Bool(false)),
+                "null" => Operand::Literal(Value::Null),
+                _ => Operand::Column(name.clone()),
+            },
+            Some(Token::Str(s)) => Operand::Literal(Value::String(s.clone())),
+            Some(Token::Num(n)) => Operand::Literal(Value::from(*n)),
+            _ => return Err(self.error("a column or a value")),
+        };
+        self.next();
+        Ok(operand)
+    }
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Ident(s) => write!(f, "column {:?}", s),
+            Token::Str(s) => write!(f, "string {:?}", s),
+            Token::Num(n) => write!(f, "number {}", n),
+            Token::Cmp(op) => write!(f, "operator {:?}", op),
+            Token::Match(_) => write!(f, "regex operator"),
+            Token::And => write!(f, "'&&'"),
+            Token::Or => write!(f, "'||'"),
+            Token::Not => write!(f, "'!'"),
+            Token::LParen => write!(f, "'('"),
+            Token::RParen => write!(f, "')'"),
+        }
+    }
+}
+
+/// Check that the columns used by `--select`, `--rename` and `--where` exist,
+/// and that `--rename` does not give two output columns the same name
+pub fn verify_filter_columns(headers: &[String], opts: &CsvFilterOpts) -> Result<()> {
+    let selected = opts.select.iter().flatten().map(|s| s.as_str());
+    let renamed = opts.rename.iter().map(|r| r.from.as_str());
+    let filtered = opts.filter.iter().flat_map(|e| e.columns());
+    for column in selected.chain(renamed).chain(filtered) {
+        if !headers.iter().any(|h| h == column) {
+            anyhow::bail!("Unknown column: {}", column);
+        }
+    }
+    let mut names = HashSet::new();
+    for name in filter_headers(headers, opts) {
+        if !names.insert(name.clone()) {
+            anyhow::bail!("Column {} already exists, cannot rename onto it", name);
+        }
+    }
+    Ok(())
+}
+
+/// Columns left after `--select` and `--rename`
+pub fn filter_headers(headers: &[String], opts: &CsvFilterOpts) -> Vec<String> {
+    opts.select
+        .as_deref()
+        .unwrap_or(headers)
+        .iter()
+        .map(
+            |column| match opts.rename.iter().find(|r| &r.from == column) {
+                Some(r) => r.to.clone(),
+                None => column.clone(),
+            },
+        )
+        .collect()
+}
+
+/// Apply `--where`, `--select` and `--rename` to a record, in this order.
+/// Returns `None` if the record is filtered out
+pub fn filter_record(record: Value, opts: &CsvFilterOpts) -> Option<Value> {
+    if let Some(filter) = &opts.filter {
+        if !filter.eval(&record) {
+            return None;
+        }
+    }
+    let Value::Object(mut map) = record else {
+        return Some(record);
+    };
+    if let Some(select) = &opts.select {
+        map = select
+            .iter()
+            .map(|name| (name.clone(), map.remove(name).unwrap_or(Value::Null)))
+            .collect::<Map<_, _>>();
+    }
+    if !opts.rename.is_empty() {
+        map = map
+            .into_iter()
+            .map(|(k, v)| match opts.rename.iter().find(|r| r.from == k) {
+                Some(r) => (r.to.clone(), v),
+                None => (k, v),
+            })
+            .collect();
+    }
+    Some(Value::Object(map))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_expr_eval() {
+        let record =
+            json!({"Name": "Gianluigi Buffon", "Position": "Goalkeeper", "Kit Number": "77"});
+        let eval = |s: &str| s.parse::<Expr>().unwrap().eval(&record);
+        assert!(eval("Position == 'Goalkeeper' && `Kit Number` > 10"));
+        assert!(eval("`Kit Number` >= 77 || Position == \"Defender\""));
+        assert!(!eval("!(Name =~ '^Gian')"));
+        assert!(eval("Name !~ 'Perin$' && Missing == null"));
+        assert!(eval("`Kit Number` < 100 && `Kit Number` != 7"));
+
+        let record = json!({"Name": "NaN", "Note": "inf"});
+        let eval = |s: &str| s.parse::<Expr>().unwrap().eval(&record);
+        assert!(eval("Name == 'NaN' && Note == 'inf'"));
+        assert!(eval("Name > 'Mario'"));
+    }
+
+    #[test]
+    fn test_expr_parse_errors() {
+        let err = |s: &str| s.parse::<Expr>().unwrap_err();
+        assert_eq!(
+            err("Position == "),
+            "expected a column or a value, found end of expression"
+        );
+        assert_eq!(err("(a > 1 && b"), "expected ')', found end of expression");
+        assert_eq!(err("a > 1 b"), "unexpected column \"b\" at position 6");
+        assert_eq!(err("a = 1"), "invalid operator at position 2");
+        assert!(err("a =~ '('").starts_with("invalid regex"));
+    }
+
+    #[test]
+    fn test_filter_record() {
+        let opts = CsvFilterOpts {
+            select: Some(vec!["Kit Number".to_string(), "Name".to_string()]),
+            rename: vec!["Kit Number=kit".parse().unwrap()],
+            filter: Some("Position == 'Goalkeeper'".parse().unwrap()),
+        };
+        let record = json!({"Name": "Mattia Perin", "Position": "Goalkeeper", "Kit Number": 37});
+        assert_eq!(
+            filter_record(record, &opts),
+            Some(json!({"kit": 37, "Name": "Mattia Perin"}))
+        );
+        let record = json!({"Name": "Leonardo Bonucci", "Position": "Centre-Back"});
+        assert_eq!(filter_record(record, &opts), None);
+
+        let headers = ["Name", "Position", "Kit Number"].map(String::from);
+        assert!(verify_filter_columns(&headers, &opts).is_ok());
+        let opts = CsvFilterOpts {
+            rename: vec!["Kit Number=Name".parse().unwrap()],
+            ..opts
+        };
+        assert!(verify_filter_columns(&headers, &opts).is_err());
+    }
+}
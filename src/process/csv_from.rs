@@ -0,0 +1,133 @@
+use anyhow::Result;
+use csv::WriterBuilder;
+use serde_json::Value;
+use std::io::Read;
+
+use crate::{
+    finish_csv, flatten_record, get_data_reader, get_data_writer, OutputFormat, WriteFinish,
+};
+
+/// Convert an array of objects in json/yaml/toml back to CSV
+pub fn process_csv_from(
+    input: &str,
+    output: &str,
+    format: OutputFormat,
+    key: Option<&str>,
+    delimiter: u8,
+    flatten: bool,
+) -> Result<()> {
+    let mut reader = get_data_reader(input)?;
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+    let mut records = parse_records(&content, format, key)?;
+    if flatten {
+        records = records.iter().map(flatten_record).collect();
+    }
+    write_csv(get_data_writer(output)?, &records, delimiter)
+}
+
+/// Parse a document and locate the array of records in it. If the root is an
+/// object, the array is taken from `key`, or from its only array value
+pub fn parse_records(content: &str, format: OutputFormat, key: Option<&str>) -> Result<Vec<Value>> {
+    let doc: Value = match format {
+        OutputFormat::Json => serde_json::from_str(content)?,
+        OutputFormat::Ndjson => content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str::<Value>)
+            .collect::<Result<_, _>>()?,
+        OutputFormat::Yaml => serde_yaml::from_str(content)?,
+        OutputFormat::Toml => toml::from_str(content)?,
+        _ => anyhow::bail!("Cannot read records from {}", format),
+    };
+    let records = match (doc, key) {
+        (Value::Object(mut map), Some(key)) => map
+            .remove(key)
+            .ok_or_else(|| anyhow::anyhow!("Key not found: {}", key))?,
+        (Value::Object(map), None) => {
+            let mut arrays = map.into_iter().filter(|(_, v)| v.is_array());
+            match (arrays.next(), arrays.next()) {
+                (Some((_, v)), None) => v,
+                (None, _) => anyhow::bail!("No array of records found"),
+                _ => anyhow::bail!("Several arrays found, use --key to pick one"),
+            }
+        }
+        (doc, _) => doc,
+    };
+    let Value::Array(records) = records else {
+        anyhow::bail!("Expected an array of records");
+    };
+    if let Some(i) = records.iter().position(|r| !r.is_object()) {
+        anyhow::bail!("Record {} is not an object", i);
+    }
+    Ok(records)
+}
+
+/// Write records as CSV, the header is the union of all keys in first-seen order
+/// and missing cells are left empty
+pub fn write_csv<W: WriteFinish>(writer: W, records: &[Value], delimiter: u8) -> Result<()> {
+    let mut headers: Vec<&str> = Vec::new();
+    for record in records.iter().filter_map(|r| r.as_object()) {
+        for key in record.keys() {
+            if !headers.contains(&key.as_str()) {
+                headers.push(key);
+            }
+        }
+    }
+
+    let mut writer = WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(writer);
+    writer.write_record(&headers)?;
+    for record in records {
+        let row = headers
+            .iter()
+            .map(|h| record.get(h).map(cell_to_string).unwrap_or_default());
+        writer.write_record(row)?;
+    }
+    finish_csv(writer)
+}
+
+/// Format a value as a CSV cell, nested values are kept as JSON
+pub fn cell_to_string(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_csv_union_of_keys() -> Result<()> {
+        let content = r#"
+[[rows]]
+name = "Buffon"
+kit = 77
+
+[[rows]]
+name = "Perin"
+active = true
+"#;
+        let records = parse_records(content, OutputFormat::Toml, None)?;
+        let mut buf = Vec::new();
+        write_csv(&mut buf, &records, b';')?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "name;kit;active\nBuffon;77;\nPerin;;true\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_records_with_key() -> Result<()> {
+        let content = r#"{"count": 1, "data": [{"a": 1}], "tags": []}"#;
+        assert!(parse_records(content, OutputFormat::Json, None).is_err());
+        let records = parse_records(content, OutputFormat::Json, Some("data"))?;
+        assert_eq!(records.len(), 1);
+        Ok(())
+    }
+}
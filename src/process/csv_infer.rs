@@ -0,0 +1,278 @@
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use csv::StringRecord;
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+use std::{fmt::Display, str::FromStr};
+
+use crate::CsvTypeOpts;
+
+const DATE_FORMATS: &[&str] = &["%Y-%m-%d", "%Y/%m/%d"];
+const DATETIME_FORMATS: &[&str] = &["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"];
+
+/// Type of a CSV column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum ColumnType {
+    Null,
+    Bool,
+    Int,
+    Float,
+    Date,
+    String,
+}
+
+impl ColumnType {
+    /// Combine the types seen in the same column into the narrowest type holding both
+    pub fn merge(self, other: Self) -> Self {
+        use ColumnType::*;
+        match (self, other) {
+            (Null, t) | (t, Null) => t,
+            (a, b) if a == b => a,
+            (Int, Float) | (Float, Int) => Float,
+            _ => String,
+        }
+    }
+
+    /// Convert a cell to a JSON value of this type, empty cells become null
+    pub fn parse_value(self, value: &str, opts: &CsvTypeOpts) -> Result<Value> {
+        let trimmed = value.trim();
+        if trimmed.is_empty() && self != ColumnType::String {
+            return Ok(Value::Null);
+        }
+        let parsed = match self {
+            ColumnType::Null => Some(Value::Null),
+            ColumnType::Bool => parse_bool(trimmed).map(Value::Bool),
+            ColumnType::Int => trimmed.parse::<i64>().ok().map(Value::from),
+            ColumnType::Float => trimmed
+                .parse::<f64>()
+                .ok()
+                .and_then(Number::from_f64)
+                .map(Value::Number),
+            ColumnType::Date => parse_date(trimmed, opts).map(Value::String),
+            ColumnType::String => Some(Value::String(value.to_string())),
+        };
+        parsed.ok_or_else(|| anyhow::anyhow!("Invalid {} value: {:?}", self, value))
+    }
+}
+
+impl FromStr for ColumnType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "null" => Ok(ColumnType::Null),
+            "bool" | "boolean" => Ok(ColumnType::Bool),
+            "int" | "integer" => Ok(ColumnType::Int),
+            "float" | "number" => Ok(ColumnType::Float),
+            "date" => Ok(ColumnType::Date),
+            "string" | "str" => Ok(ColumnType::String),
+            _ => Err(format!("Invalid column type: {}", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for ColumnType {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<ColumnType> for String {
+    fn from(t: ColumnType) -> Self {
+        t.to_string()
+    }
+}
+
+impl Display for ColumnType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ColumnType::Null => "null",
+            ColumnType::Bool => "bool",
+            ColumnType::Int => "int",
+            ColumnType::Float => "float",
+            ColumnType::Date => "date",
+            ColumnType::String => "string",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Detect the type of a single cell
+pub fn detect_type(value: &str, opts: &CsvTypeOpts) -> ColumnType {
+    let value = value.trim();
+    if value.is_empty() {
+        ColumnType::Null
+    } else if parse_bool(value).is_some() {
+        ColumnType::Bool
+    } else if is_number(value) && value.parse::<i64>().is_ok() {
+        ColumnType::Int
+    } else if is_number(value) && value.parse::<f64>().is_ok_and(f64::is_finite) {
+        ColumnType::Float
+    } else if opts.infer_dates && parse_date(value, opts).is_some() {
+        ColumnType::Date
+    } else {
+        ColumnType::String
+    }
+}
+
+/// Resolve the type of every column: overrides first, then inference if enabled,
+/// otherwise everything stays a string
+pub fn infer_column_types(
+    headers: &[String],
+    records: &[StringRecord],
+    opts: &CsvTypeOpts,
+) -> Result<Vec<ColumnType>> {
+    let width = records
+        .iter()
+        .map(|r| r.len())
+        .max()
+        .unwrap_or(0)
+        .max(headers.len());
+    let mut types = vec![ColumnType::Null; width];
+    if opts.infer_types {
+        for record in records {
+            for (i, value) in record.iter().enumerate() {
+                types[i] = types[i].merge(detect_type(value, opts));
+            }
+        }
+    }
+    for t in types.iter_mut() {
+        if !opts.infer_types || *t == ColumnType::Null {
+            *t = ColumnType::String;
+        }
+    }
+    for column in &opts.types {
+        let Some(i) = headers.iter().position(|h| h == &column.name) else {
+            anyhow::bail!("Unknown column in type override: {}", column.name);
+        };
+        types[i] = column.column_type;
+    }
+    Ok(types)
+}
+
+/// Convert the fields of a record according to the column types, empty cells
+/// become null when inference is on. Cells not matching an inferred type are
+/// kept as strings, only overridden types are enforced
+pub fn typed_values(
+    headers: &[String],
+    types: &[ColumnType],
+    record: &StringRecord,
+    opts: &CsvTypeOpts,
+) -> Result<Vec<Value>> {
+    record
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            if opts.infer_types && field.trim().is_empty() {
+                return Ok(Value::Null);
+            }
+            let t = types.get(i).copied().unwrap_or(ColumnType::String);
+            let column = headers.get(i).map(|h| h.as_str()).unwrap_or_default();
+            match t.parse_value(field, opts) {
+                Ok(value) => Ok(value),
+                Err(_) if !opts.types.iter().any(|o| o.name == column) => {
+                    Ok(Value::String(field.to_string()))
+                }
+                Err(e) => {
+                    let line = record.position().map(|p| p.line()).unwrap_or_default();
+                    anyhow::bail!("{} in column {:?} at line {}", e, column, line)
+                }
+            }
+        })
+        .collect()
+}
+
+fn is_number(value: &str) -> bool {
+    let digits = value.trim_start_matches(['-', '+']);
+    // keep values like zip codes or ids with leading zeros as strings
+    let leading_zero = digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0.");
+    !leading_zero
+        && digits.starts_with(|c: char| c.is_ascii_digit())
+        && value
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parse a date or datetime and normalize it to ISO 8601
+fn parse_date(value: &str, opts: &CsvTypeOpts) -> Option<String> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Some(dt.to_rfc3339());
+    }
+    let formats = opts
+        .date_formats
+        .iter()
+        .map(|f| f.as_str())
+        .chain(DATE_FORMATS.iter().copied());
+    for format in formats {
+        if let Ok(date) = NaiveDate::parse_from_str(value, format) {
+            return Some(date.format("%Y-%m-%d").to_string());
+        }
+    }
+    let formats = opts
+        .date_formats
+        .iter()
+        .map(|f| f.as_str())
+        .chain(DATETIME_FORMATS.iter().copied());
+    for format in formats {
+        if let Ok(dt) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(dt.format("%Y-%m-%dT%H:%M:%S").to_string());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_detect_type() {
+        let opts = CsvTypeOpts {
+            infer_types: true,
+            infer_dates: true,
+            ..Default::default()
+        };
+        assert_eq!(detect_type("37", &opts), ColumnType::Int);
+        assert_eq!(detect_type("-1.5e3", &opts), ColumnType::Float);
+        assert_eq!(detect_type("TRUE", &opts), ColumnType::Bool);
+        assert_eq!(detect_type("", &opts), ColumnType::Null);
+        assert_eq!(detect_type("2019-04-18", &opts), ColumnType::Date);
+        assert_eq!(detect_type("007", &opts), ColumnType::String);
+        assert_eq!(detect_type("inf", &opts), ColumnType::String);
+        assert_eq!(detect_type("Italy", &opts), ColumnType::String);
+    }
+
+    #[test]
+    fn test_infer_column_types_with_override() -> Result<()> {
+        let opts = CsvTypeOpts {
+            infer_types: true,
+            types: vec!["id=string".parse().unwrap()],
+            ..Default::default()
+        };
+        let headers = vec!["id".to_string(), "score".to_string(), "ok".to_string()];
+        let records = vec![
+            StringRecord::from(vec!["1", "1", "true"]),
+            StringRecord::from(vec!["2", "2.5", ""]),
+        ];
+        let types = infer_column_types(&headers, &records, &opts)?;
+        assert_eq!(
+            types,
+            [ColumnType::String, ColumnType::Float, ColumnType::Bool]
+        );
+        let values = typed_values(&headers, &types, &records[1], &opts)?;
+        assert_eq!(values, [json!("2"), json!(2.5), Value::Null]);
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+use anyhow::Result;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+use crate::{
+    column_index, csv_headers, csv_input, output_writer, CsvReaderOpts, CsvWriterOpts, JoinKind,
+    JoinOn, OutputFormat, RecordWriter,
+};
+
+/// Join two CSV files on a key column. The right file is loaded in memory and
+/// the left one is streamed
+#[allow(clippy::too_many_arguments)]
+pub fn process_csv_join(
+    left: &str,
+    right: &str,
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    on: &JoinOn,
+    kind: JoinKind,
+    writer_opts: &CsvWriterOpts,
+) -> Result<()> {
+    let mut writer = output_writer(output, format, writer_opts)?;
+    join_records(left, right, opts, on, kind, writer.as_mut())?;
+    writer.finish()
+}
+
+/// Write the joined records. Right columns sharing a name with a left column get a
+/// `_right` suffix, numbered when that name is taken too, the right key column is left out when both keys have the same name.
+/// Rows with an empty key are never matched
+pub fn join_records(
+    left: &str,
+    right: &str,
+    opts: &CsvReaderOpts,
+    on: &JoinOn,
+    kind: JoinKind,
+    writer: &mut dyn RecordWriter,
+) -> Result<()> {
+    let mut right_reader = csv_input(right, opts)?;
+    let right_headers = csv_headers(&mut right_reader, opts)?;
+    let right_key = column_index(&right_headers, &on.right)?;
+    let right_rows = right_reader.records().collect::<Result<Vec<_>, _>>()?;
+    let mut index: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, row) in right_rows.iter().enumerate() {
+        // like NULL in SQL, an empty key never matches anything
+        match row.get(right_key) {
+            Some(key) if !key.is_empty() => index.entry(key).or_default().push(i),
+            _ => {}
+        }
+    }
+
+    let mut left_reader = csv_input(left, opts)?;
+    let left_headers = csv_headers(&mut left_reader, opts)?;
+    let left_key = column_index(&left_headers, &on.left)?;
+    let shared_key = on.left == on.right;
+    // output name of every right column, None for the merged key column
+    let mut right_names = Vec::<Option<String>>::with_capacity(right_headers.len());
+    for (i, h) in right_headers.iter().enumerate() {
+        let name = if shared_key && i == right_key {
+            None
+        } else if left_headers.contains(h) {
+            // the suffixed name must not be a column of either side either
+            let taken = |name: &String| {
+                left_headers.contains(name)
+                    || right_headers.contains(name)
+                    || right_names.iter().flatten().any(|n| n == name)
+            };
+            let mut name = format!("{}_right", h);
+            let mut n = 1;
+            while taken(&name) {
+                n += 1;
+                name = format!("{}_right{}", h, n);
+            }
+            Some(name)
+        } else {
+            Some(h.clone())
+        };
+        right_names.push(name);
+    }
+    let headers = left_headers
+        .iter()
+        .chain(right_names.iter().flatten())
+        .cloned()
+        .collect::<Vec<_>>();
+    writer.write_headers(&headers)?;
+
+    let joined = |left: Option<&csv::StringRecord>, right: Option<&csv::StringRecord>| {
+        let mut map = Map::new();
+        for (i, h) in left_headers.iter().enumerate() {
+            let value = match (left, right) {
+                (Some(left), _) => left.get(i).map(|v| Value::String(v.to_string())),
+                // unmatched right rows still fill the shared key column
+                (None, Some(right)) if shared_key && i == left_key => {
+                    right.get(right_key).map(|v| Value::String(v.to_string()))
+                }
+                _ => None,
+            };
+            map.insert(h.clone(), value.unwrap_or(Value::Null));
+        }
+        for (i, name) in right_names.iter().enumerate() {
+            if let Some(name) = name {
+                let value = right
+                    .and_then(|r| r.get(i))
+                    .map(|v| Value::String(v.to_string()));
+                map.insert(name.clone(), value.unwrap_or(Value::Null));
+            }
+        }
+        Value::Object(map)
+    };
+
+    let mut matched = vec![false; right_rows.len()];
+    for record in left_reader.records() {
+        let record = record?;
+        let key = record.get(left_key).unwrap_or_default();
+        match index.get(key) {
+            Some(rows) => {
+                for &i in rows {
+                    matched[i] = true;
+                    writer.write_record(&joined(Some(&record), Some(&right_rows[i])))?;
+                }
+            }
+            None if kind != JoinKind::Inner => writer.write_record(&joined(Some(&record), None))?,
+            None => {}
+        }
+    }
+    if kind == JoinKind::Outer {
+        for (row, _) in right_rows.iter().zip(&matched).filter(|(_, m)| !**m) {
+            writer.write_record(&joined(None, Some(row)))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serialize_records;
+    use serde_json::json;
+    use std::io::Write;
+
+    fn csv_file(content: &str) -> Result<tempfile::NamedTempFile> {
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(content.as_bytes())?;
+        Ok(file)
+    }
+
+    #[test]
+    fn test_join_kinds() -> Result<()> {
+        let left = csv_file("id,name\n1,Buffon\n2,Perin\n3,Chiellini\n,Rugani\n")?;
+        let right = csv_file("id,name,kit\n1,Gigi,77\n3,Giorgio,3\n4,Pjanic,5\n,Kean,18\n")?;
+        let (left, right) = (
+            left.path().to_str().unwrap(),
+            right.path().to_str().unwrap(),
+        );
+        let on = "id".parse::<JoinOn>().unwrap();
+        let join = |kind| -> Result<Vec<Value>> {
+            let mut buf = Vec::new();
+            let mut writer =
+                crate::record_writer(&mut buf, OutputFormat::Ndjson, &Default::default());
+            join_records(left, right, &Default::default(), &on, kind, writer.as_mut())?;
+            writer.finish()?;
+            drop(writer);
+            String::from_utf8(buf)?
+                .lines()
+                .map(|l| Ok(serde_json::from_str(l)?))
+                .collect()
+        };
+
+        let inner = join(JoinKind::Inner)?;
+        assert_eq!(inner.len(), 2);
+        assert_eq!(
+            inner[0],
+            json!({"id": "1", "name": "Buffon", "name_right": "Gigi", "kit": "77"})
+        );
+        let left_join = join(JoinKind::Left)?;
+        assert_eq!(left_join.len(), 4);
+        assert_eq!(left_join[1]["kit"], Value::Null);
+        assert_eq!(left_join[3]["name_right"], Value::Null);
+        let outer = join(JoinKind::Outer)?;
+        assert_eq!(outer.len(), 6);
+        assert_eq!(
+            outer[4],
+            json!({"id": "4", "name": null, "name_right": "Pjanic", "kit": "5"})
+        );
+        assert!(
+            serialize_records(&outer, OutputFormat::Csv, &Default::default())?
+                .starts_with("id,name,name_right,kit\n")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_suffix_collision() -> Result<()> {
+        let left = csv_file("id,name,name_right\n1,Buffon,Gigi\n")?;
+        let right = csv_file("id,name,name_right\n1,Perin,Mattia\n")?;
+        let on = "id".parse::<JoinOn>().unwrap();
+        let mut buf = Vec::new();
+        let mut writer = crate::record_writer(&mut buf, OutputFormat::Csv, &Default::default());
+        join_records(
+            left.path().to_str().unwrap(),
+            right.path().to_str().unwrap(),
+            &Default::default(),
+            &on,
+            JoinKind::Inner,
+            writer.as_mut(),
+        )?;
+        writer.finish()?;
+        drop(writer);
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "id,name,name_right,name_right2,name_right_right\n1,Buffon,Gigi,Perin,Mattia\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::Result;
+use std::io::Read;
+
+use crate::{
+    map_columns, Blake3, CsvReaderOpts, CsvWriterOpts, KeyLoader, MaskMode, OutputFormat,
+    TextSigner,
+};
+
+/// Bytes of the keyed hash kept in a token, 128 bits
+const TOKEN_BYTES: usize = 16;
+/// Characters left as they are by partial masking
+const PARTIAL_KEEP: usize = 4;
+const REDACTED: &str = "[REDACTED]";
+
+/// Replaces the values of the masked columns
+pub struct Masker {
+    mode: MaskMode,
+    key: Option<Blake3>,
+}
+
+impl Masker {
+    /// The token and fake modes need the BLAKE3 key file
+    pub fn new(mode: MaskMode, key: Option<&str>) -> Result<Self> {
+        let key = match (mode, key) {
+            (MaskMode::Token | MaskMode::Fake, None) => {
+                anyhow::bail!("--key is required to mask with {}", mode)
+            }
+            (_, Some(key)) => Some(Blake3::load(key)?),
+            (_, None) => None,
+        };
+        Ok(Self { mode, key })
+    }
+
+    /// Mask a value, empty values stay empty. Tokens and fake values only depend
+    /// on the key and the value, so masked columns can still be joined
+    pub fn mask(&self, value: &str) -> Result<String> {
+        if value.is_empty() {
+            return Ok(String::new());
+        }
+        Ok(match (self.mode, &self.key) {
+            (MaskMode::Token, Some(key)) => {
+                let hash = key.sign(&mut value.as_bytes())?;
+                hash[..TOKEN_BYTES]
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect()
+            }
+            (MaskMode::Fake, Some(key)) => {
+                let mut hasher = key.keyed_hasher();
+                hasher.update(b"fake\0").update(value.as_bytes());
+                let mut bytes = vec![0; value.chars().count()];
+                hasher.finalize_xof().read_exact(&mut bytes)?;
+                value
+                    .chars()
+                    .zip(bytes)
+                    .map(|(c, b)| fake_char(c, b))
+                    .collect()
+            }
+            (MaskMode::Redact, _) => REDACTED.to_string(),
+            (MaskMode::Partial, _) => {
+                let len = value.chars().count();
+                // short values would be shown whole, so they are hidden completely
+                let hidden = if len > PARTIAL_KEEP {
+                    len - PARTIAL_KEEP
+                } else {
+                    len
+                };
+                value
+                    .chars()
+                    .enumerate()
+                    .map(|(i, c)| if i < hidden { '*' } else { c })
+                    .collect()
+            }
+            (MaskMode::Token | MaskMode::Fake, None) => unreachable!("checked in Masker::new"),
+        })
+    }
+}
+
+/// Mask the values of some columns, the other columns are written as they are
+#[allow(clippy::too_many_arguments)]
+pub fn process_csv_mask(
+    input: &str,
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    columns: &[String],
+    mode: MaskMode,
+    key: Option<&str>,
+    writer_opts: &CsvWriterOpts,
+) -> Result<()> {
+    let masker = Masker::new(mode, key)?;
+    map_columns(
+        input,
+        output,
+        format,
+        opts,
+        columns,
+        writer_opts,
+        |_, value| masker.mask(value),
+    )
+}
+
+/// Digits become digits and letters letters of the same case, anything else is kept
+fn fake_char(c: char, b: u8) -> char {
+    if c.is_numeric() {
+        (b'0' + b % 10) as char
+    } else if c.is_uppercase() {
+        (b'A' + b % 26) as char
+    } else if c.is_alphabetic() {
+        (b'a' + b % 26) as char
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_modes() -> Result<()> {
+        let key = Some("fixtures/blake3.txt");
+        let token = Masker::new(MaskMode::Token, key)?;
+        let masked = token.mask("Gianluigi Buffon")?;
+        assert_eq!(masked.len(), TOKEN_BYTES * 2);
+        assert_eq!(masked, token.mask("Gianluigi Buffon")?);
+        assert_ne!(masked, token.mask("Mattia Perin")?);
+        assert_eq!(token.mask("")?, "");
+
+        let fake = Masker::new(MaskMode::Fake, key)?;
+        let masked = fake.mask("Jan 28, 1978 (41)")?;
+        assert_ne!(masked, "Jan 28, 1978 (41)");
+        let re = regex::Regex::new(r"^[A-Z][a-z]{2} \d{2}, \d{4} \(\d{2}\)$")?;
+        assert!(re.is_match(&masked));
+
+        let partial = Masker::new(MaskMode::Partial, None)?;
+        assert_eq!(partial.mask("4111111111111111")?, "************1111");
+        assert_eq!(partial.mask("Kean")?, "****");
+        assert_eq!(Masker::new(MaskMode::Redact, None)?.mask("x")?, REDACTED);
+        assert!(Masker::new(MaskMode::Token, None).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+use anyhow::Result;
+use serde_json::{Map, Value};
+
+/// Largest array index accepted in a column path, guards against huge allocations
+const MAX_ARRAY_INDEX: usize = 10_000;
+
+/// A step of a column path like `address.city` or `tags[0]`
+#[derive(Debug, PartialEq)]
+enum PathSegment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+/// Flatten nested objects and arrays into a single object with dotted and
+/// indexed keys, e.g. `{"address": {"city": "Turin"}}` becomes `{"address.city": "Turin"}`.
+/// Empty objects and arrays are kept as they are
+pub fn flatten_record(record: &Value) -> Value {
+    let mut map = Map::new();
+    match record {
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                flatten_into(key.clone(), value, &mut map);
+            }
+        }
+        value => {
+            map.insert(String::new(), value.clone());
+        }
+    }
+    Value::Object(map)
+}
+
+fn flatten_into(prefix: String, value: &Value, map: &mut Map<String, Value>) {
+    match value {
+        Value::Object(fields) if !fields.is_empty() => {
+            for (key, value) in fields {
+                flatten_into(format!("{}.{}", prefix, key), value, map);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for (i, value) in items.iter().enumerate() {
+                flatten_into(format!("{}[{}]", prefix, i), value, map);
+            }
+        }
+        value => {
+            map.insert(prefix, value.clone());
+        }
+    }
+}
+
+/// Rebuild nested objects and arrays from dotted and indexed keys, the inverse of
+/// [`flatten_record`]. The cells `[]` and `{}` are read back as empty containers.
+/// Null fields are kept, except trailing null array items, so records with fewer
+/// array items than the header keep their original length
+pub fn unflatten_record(record: &Value) -> Result<Value> {
+    let Value::Object(fields) = record else {
+        anyhow::bail!("Record is not an object");
+    };
+    let mut root = Value::Object(Map::new());
+    for (key, value) in fields {
+        let value = match value.as_str() {
+            Some("[]") => Value::Array(Vec::new()),
+            Some("{}") => Value::Object(Map::new()),
+            _ => value.clone(),
+        };
+        insert(&mut root, &parse_path(key), value)
+            .map_err(|_| anyhow::anyhow!("Column {:?} conflicts with another column", key))?;
+    }
+    trim_arrays(&mut root);
+    Ok(root)
+}
+
+/// Drop the null items at the end of every array
+fn trim_arrays(value: &mut Value) {
+    match value {
+        Value::Array(items) => {
+            while items.last().is_some_and(Value::is_null) {
+                items.pop();
+            }
+            items.iter_mut().for_each(trim_arrays);
+        }
+        Value::Object(map) => map.values_mut().for_each(trim_arrays),
+        _ => {}
+    }
+}
+
+/// Split a key into path segments. Keys that are not valid paths, like `a..b`,
+/// are kept as a single key
+fn parse_path(key: &str) -> Vec<PathSegment<'_>> {
+    let mut path = Vec::new();
+    for part in key.split('.') {
+        let (name, mut rest) = match part.find('[') {
+            Some(i) => part.split_at(i),
+            None => (part, ""),
+        };
+        if name.is_empty() && (path.is_empty() || rest.is_empty()) {
+            return vec![PathSegment::Key(key)];
+        }
+        if !name.is_empty() {
+            path.push(PathSegment::Key(name));
+        }
+        while !rest.is_empty() {
+            let index = rest
+                .strip_prefix('[')
+                .and_then(|r| r.split_once(']'))
+                .and_then(|(index, r)| Some((index.parse::<usize>().ok()?, r)));
+            match index {
+                Some((index, r)) if index <= MAX_ARRAY_INDEX => {
+                    path.push(PathSegment::Index(index));
+                    rest = r;
+                }
+                _ => return vec![PathSegment::Key(key)],
+            }
+        }
+    }
+    path
+}
+
+/// Set the value at `path`, creating objects and arrays on the way. Fails if a
+/// value is already there or a segment does not match the existing container.
+/// A null never replaces anything
+fn insert(target: &mut Value, path: &[PathSegment], value: Value) -> Result<(), ()> {
+    let Some((segment, rest)) = path.split_first() else {
+        return match target {
+            Value::Null => {
+                *target = value;
+                Ok(())
+            }
+            _ if value.is_null() => Ok(()),
+            _ => Err(()),
+        };
+    };
+    if value.is_null() && !matches!(target, Value::Null | Value::Object(_) | Value::Array(_)) {
+        return Ok(());
+    }
+    let child = match (segment, &mut *target) {
+        (PathSegment::Key(_), Value::Null) => {
+            *target = Value::Object(Map::new());
+            return insert(target, path, value);
+        }
+        (PathSegment::Index(_), Value::Null) => {
+            *target = Value::Array(Vec::new());
+            return insert(target, path, value);
+        }
+        (PathSegment::Key(key), Value::Object(map)) => {
+            map.entry(key.to_string()).or_insert(Value::Null)
+        }
+        (PathSegment::Index(index), Value::Array(items)) => {
+            if items.len() <= *index {
+                items.resize(index + 1, Value::Null);
+            }
+            &mut items[*index]
+        }
+        _ => return Err(()),
+    };
+    insert(child, rest, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_flatten_round_trip() -> Result<()> {
+        let record = json!({
+            "name": "Buffon",
+            "address": {"city": "Turin", "geo": {"lat": 45.07}},
+            "tags": ["gk", {"since": 2001}],
+            "clubs": [],
+            "kit": 77
+        });
+        let flat = flatten_record(&record);
+        assert_eq!(
+            flat,
+            json!({
+                "name": "Buffon",
+                "address.city": "Turin",
+                "address.geo.lat": 45.07,
+                "tags[0]": "gk",
+                "tags[1].since": 2001,
+                "clubs": [],
+                "kit": 77
+            })
+        );
+        assert_eq!(unflatten_record(&flat)?, record);
+
+        // as read back from CSV with --infer-types
+        let cells = json!({
+            "name": "Buffon",
+            "address.city": null,
+            "tags[0]": "gk",
+            "tags[1]": null,
+            "clubs": "[]",
+            "meta": "{}"
+        });
+        assert_eq!(
+            unflatten_record(&cells)?,
+            json!({
+                "name": "Buffon",
+                "address": {"city": null},
+                "tags": ["gk"],
+                "clubs": [],
+                "meta": {}
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unflatten_paths() -> Result<()> {
+        let record = json!({"m[1][0]": 1, "a.b": null, "tags[2]": "x", "x..y": 2, "n": null});
+        assert_eq!(
+            unflatten_record(&record)?,
+            json!({"m": [null, [1]], "a": {"b": null}, "tags": [null, null, "x"], "x..y": 2, "n": null})
+        );
+        assert!(unflatten_record(&json!({"a": 1, "a.b": 2})).is_err());
+        assert!(unflatten_record(&json!({"a.b": 1, "a[0]": 2})).is_err());
+        Ok(())
+    }
+}
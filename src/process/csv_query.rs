@@ -0,0 +1,1329 @@
+use anyhow::Result;
+use regex::Regex;
+use serde_json::{Map, Value};
+use sqlparser::{
+    ast::{
+        BinaryOperator, DuplicateTreatment, Expr, Function, FunctionArg, FunctionArgExpr,
+        FunctionArguments, GroupByExpr, Ident, JoinConstraint, JoinOperator, Query, Select,
+        SelectItem, SetExpr, Statement, TableFactor, TableWithJoins, TrimWhereField, UnaryOperator,
+        Value as SqlValue,
+    },
+    dialect::GenericDialect,
+    parser::Parser,
+};
+use std::{cmp::Ordering, collections::HashMap, path::Path, rc::Rc};
+
+use crate::{
+    cell_to_string, csv_records, output_writer, CsvReaderOpts, CsvTypeOpts, CsvWriterOpts,
+    OutputFormat,
+};
+
+const AGGREGATES: [&str; 5] = ["count", "sum", "avg", "min", "max"];
+
+/// A CSV file registered as a table, named after the file stem unless given
+pub struct QuerySource<'a> {
+    pub name: Option<&'a str>,
+    pub path: &'a str,
+}
+
+/// Run a SQL query over CSV files and write the result
+pub fn process_csv_query(
+    sql: &str,
+    sources: &[QuerySource],
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    writer_opts: &CsvWriterOpts,
+) -> Result<()> {
+    let (names, records) = query_records(sql, sources, opts)?;
+    let mut writer = output_writer(output, format, writer_opts)?;
+    writer.write_headers(&names)?;
+    for record in &records {
+        writer.write_record(record)?;
+    }
+    writer.finish()
+}
+
+/// Run a SELECT over the CSV files, supporting WHERE, GROUP BY with count, sum, avg,
+/// min and max, HAVING, ORDER BY, LIMIT, OFFSET and inner, left, right, full and cross joins.
+/// Returns the names of the selected columns with the records
+pub fn query_records(
+    sql: &str,
+    sources: &[QuerySource],
+    opts: &CsvReaderOpts,
+) -> Result<(Vec<String>, Vec<Value>)> {
+    let mut statements = Parser::parse_sql(&GenericDialect {}, sql)?;
+    let query = match (statements.pop(), statements.is_empty()) {
+        (Some(Statement::Query(query)), true) => query,
+        _ => anyhow::bail!("Expected a single SELECT statement"),
+    };
+    let tables = sources
+        .iter()
+        .map(|source| load_table(source, opts))
+        .collect::<Result<Vec<_>>>()?;
+    run_query(&query, &tables)
+}
+
+/// Column of a relation, qualified by the name or alias of its table
+struct Column {
+    table: String,
+    name: String,
+}
+
+/// Rows with their columns, a loaded table or the result of joins. The rows of a
+/// table are shared by every reference to it
+struct Relation {
+    columns: Vec<Column>,
+    rows: Rc<Vec<Vec<Value>>>,
+}
+
+struct Table {
+    name: String,
+    relation: Relation,
+}
+
+/// Rows an expression is evaluated against, a single row or a group of rows
+#[derive(Clone, Copy)]
+enum Context<'a> {
+    Row(&'a [Value]),
+    Group(&'a [&'a [Value]]),
+}
+
+/// An output column of the SELECT list
+enum Projection<'a> {
+    Expr(&'a Expr),
+    Column(usize),
+}
+
+fn load_table(source: &QuerySource, opts: &CsvReaderOpts) -> Result<Table> {
+    let name = match source.name {
+        Some(name) => name.to_string(),
+        None => table_name(source.path)?,
+    };
+    let type_opts = CsvTypeOpts {
+        infer_types: true,
+        ..Default::default()
+    };
+    let (headers, records) = csv_records(source.path, opts, &type_opts, usize::MAX)?;
+    let rows = records
+        .map(|record| {
+            let record = record?;
+            Ok(headers
+                .iter()
+                .map(|h| record.get(h).cloned().unwrap_or(Value::Null))
+                .collect())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let columns = headers
+        .into_iter()
+        .map(|h| Column {
+            table: name.clone(),
+            name: h,
+        })
+        .collect();
+    Ok(Table {
+        name,
+        relation: Relation {
+            columns,
+            rows: Rc::new(rows),
+        },
+    })
+}
+
+/// Table name of a file, `assets/juventus.csv` is `juventus`
+fn table_name(path: &str) -> Result<String> {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.split('.').next())
+        .filter(|name| !name.is_empty())
+        .map(|name| name.to_string())
+        .ok_or_else(|| anyhow::anyhow!("Cannot name the table of {}, use NAME=PATH", path))
+}
+
+fn run_query(query: &Query, tables: &[Table]) -> Result<(Vec<String>, Vec<Value>)> {
+    if query.with.is_some() {
+        anyhow::bail!("WITH is not supported");
+    }
+    let SetExpr::Select(select) = query.body.as_ref() else {
+        anyhow::bail!("Only SELECT queries are supported");
+    };
+    let relation = from_relation(&select.from, tables)?;
+    let names = projection_names(select, &relation)?;
+    let projections = projections(select, &relation)?;
+    let eval = Evaluator {
+        columns: &relation.columns,
+    };
+
+    let rows = match &select.selection {
+        Some(filter) => relation
+            .rows
+            .iter()
+            .filter_map(|row| match eval.eval(filter, Context::Row(row)) {
+                Ok(v) if truthy(&v) => Some(Ok(row.as_slice())),
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => relation.rows.iter().map(|r| r.as_slice()).collect(),
+    };
+
+    let group_by = match &select.group_by {
+        GroupByExpr::Expressions(exprs, _) => exprs.as_slice(),
+        GroupByExpr::All(_) => anyhow::bail!("GROUP BY ALL is not supported"),
+    };
+    let aggregate = !group_by.is_empty()
+        || projections.iter().any(|p| match p {
+            Projection::Expr(expr) => contains_aggregate(expr),
+            Projection::Column(_) => false,
+        })
+        || select.having.as_ref().is_some_and(contains_aggregate);
+
+    let order_by = query
+        .order_by
+        .as_ref()
+        .map(|o| o.exprs.as_slice())
+        .unwrap_or_default();
+    let project = |ctx: Context| -> Result<Option<(Vec<Value>, Vec<Value>)>> {
+        if let Some(having) = &select.having {
+            if !truthy(&eval.eval(having, ctx)?) {
+                return Ok(None);
+            }
+        }
+        let values = projections
+            .iter()
+            .map(|p| match p {
+                Projection::Expr(expr) => eval.eval(expr, ctx),
+                Projection::Column(i) => Ok(eval.column(*i, ctx)),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let keys = order_by
+            .iter()
+            .map(|o| order_key(&o.expr, &names, &values, &eval, ctx))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some((values, keys)))
+    };
+
+    let mut results = Vec::new();
+    if aggregate {
+        let mut index: HashMap<String, usize> = HashMap::new();
+        let mut groups: Vec<Vec<&[Value]>> = Vec::new();
+        if group_by.is_empty() {
+            groups.push(rows);
+        } else {
+            for row in rows {
+                let key = group_by
+                    .iter()
+                    .map(|expr| eval.eval(expr, Context::Row(row)))
+                    .collect::<Result<Vec<_>>>()?;
+                let key = serde_json::to_string(&key)?;
+                let i = *index.entry(key).or_insert_with(|| {
+                    groups.push(Vec::new());
+                    groups.len() - 1
+                });
+                groups[i].push(row);
+            }
+        }
+        for group in &groups {
+            results.extend(project(Context::Group(group))?);
+        }
+    } else {
+        if select.having.is_some() {
+            anyhow::bail!("HAVING needs GROUP BY or an aggregate function");
+        }
+        for row in rows {
+            results.extend(project(Context::Row(row))?);
+        }
+    }
+
+    if select.distinct.is_some() {
+        let mut seen = std::collections::HashSet::new();
+        results
+            .retain(|(values, _)| seen.insert(serde_json::to_string(values).unwrap_or_default()));
+    }
+    if !order_by.is_empty() {
+        results.sort_by(|(_, a), (_, b)| {
+            order_by
+                .iter()
+                .zip(a.iter().zip(b))
+                .map(|(o, (a, b))| {
+                    let desc = o.asc == Some(false);
+                    // NULLs are larger than any value, like PostgreSQL
+                    let nulls_first = o.nulls_first.unwrap_or(desc);
+                    match (a.is_null(), b.is_null()) {
+                        (true, true) => Ordering::Equal,
+                        (true, false) if nulls_first => Ordering::Less,
+                        (true, false) => Ordering::Greater,
+                        (false, true) if nulls_first => Ordering::Greater,
+                        (false, true) => Ordering::Less,
+                        _ => {
+                            let ordering = compare(a, b).unwrap_or(Ordering::Equal);
+                            if desc {
+                                ordering.reverse()
+                            } else {
+                                ordering
+                            }
+                        }
+                    }
+                })
+                .find(|o| o.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+    }
+
+    let offset = match &query.offset {
+        Some(offset) => constant_count(&offset.value, "OFFSET")?,
+        None => 0,
+    };
+    let limit = match &query.limit {
+        Some(limit) => constant_count(limit, "LIMIT")?,
+        None => usize::MAX,
+    };
+    let records = results
+        .into_iter()
+        .skip(offset)
+        .take(limit)
+        .map(|(values, _)| Value::Object(names.iter().cloned().zip(values).collect::<Map<_, _>>()))
+        .collect();
+    Ok((names, records))
+}
+
+/// Join every table of the FROM clause, a comma is a cross join
+fn from_relation(from: &[TableWithJoins], tables: &[Table]) -> Result<Relation> {
+    let mut result: Option<Relation> = None;
+    for item in from {
+        let mut relation = table_relation(&item.relation, tables)?;
+        for join in &item.joins {
+            let right = table_relation(&join.relation, tables)?;
+            let (constraint, left_outer, right_outer) = match &join.join_operator {
+                JoinOperator::Inner(c) => (c, false, false),
+                JoinOperator::LeftOuter(c) => (c, true, false),
+                JoinOperator::RightOuter(c) => (c, false, true),
+                JoinOperator::FullOuter(c) => (c, true, true),
+                JoinOperator::CrossJoin => (&JoinConstraint::None, false, false),
+                op => anyhow::bail!("Unsupported join: {:?}", op),
+            };
+            relation = join_relations(relation, right, constraint, left_outer, right_outer)?;
+        }
+        result = Some(match result {
+            Some(left) => join_relations(left, relation, &JoinConstraint::None, false, false)?,
+            None => relation,
+        });
+    }
+    // SELECT without FROM works on a single empty row
+    Ok(result.unwrap_or(Relation {
+        columns: Vec::new(),
+        rows: Rc::new(vec![Vec::new()]),
+    }))
+}
+
+fn table_relation(factor: &TableFactor, tables: &[Table]) -> Result<Relation> {
+    let TableFactor::Table { name, alias, .. } = factor else {
+        anyhow::bail!("Unsupported table: {}", factor);
+    };
+    let table_name = name.0.last().map(|i| i.value.as_str()).unwrap_or_default();
+    let table = tables
+        .iter()
+        .find(|t| t.name == table_name)
+        .or_else(|| {
+            tables
+                .iter()
+                .find(|t| t.name.eq_ignore_ascii_case(table_name))
+        })
+        .ok_or_else(|| anyhow::anyhow!("Unknown table: {}", name))?;
+    let qualifier = alias
+        .as_ref()
+        .map(|a| a.name.value.clone())
+        .unwrap_or_else(|| table.name.clone());
+    Ok(Relation {
+        columns: table
+            .relation
+            .columns
+            .iter()
+            .map(|c| Column {
+                table: qualifier.clone(),
+                name: c.name.clone(),
+            })
+            .collect(),
+        rows: Rc::clone(&table.relation.rows),
+    })
+}
+
+/// Join two relations. A single equality between a left and a right column is
+/// answered with a hash lookup, other conditions are checked on every pair of rows
+fn join_relations(
+    left: Relation,
+    right: Relation,
+    constraint: &JoinConstraint,
+    left_outer: bool,
+    right_outer: bool,
+) -> Result<Relation> {
+    let left_width = left.columns.len();
+    let right_width = right.columns.len();
+    let mut columns = left.columns;
+    columns.extend(right.columns);
+    let eval = Evaluator { columns: &columns };
+
+    let using;
+    let condition = match constraint {
+        JoinConstraint::On(expr) => Some(expr),
+        JoinConstraint::Using(idents) => {
+            // qualify the column with the table holding it on each side
+            let qualified = |side: &[Column], ident: &Ident| {
+                let table = side
+                    .iter()
+                    .find(|c| ident_matches(ident, &c.name))
+                    .map(|c| c.table.clone())
+                    .ok_or_else(|| anyhow::anyhow!("Unknown column in USING: {}", ident))?;
+                Ok::<_, anyhow::Error>(Expr::CompoundIdentifier(vec![
+                    Ident::with_quote('"', table),
+                    ident.clone(),
+                ]))
+            };
+            let mut conditions = idents
+                .iter()
+                .map(|ident| {
+                    Ok(Expr::BinaryOp {
+                        left: Box::new(qualified(&columns[..left_width], ident)?),
+                        op: BinaryOperator::Eq,
+                        right: Box::new(qualified(&columns[left_width..], ident)?),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?
+                .into_iter();
+            let first = conditions
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("USING needs a column"))?;
+            using = conditions.fold(first, |acc, c| Expr::BinaryOp {
+                left: Box::new(acc),
+                op: BinaryOperator::And,
+                right: Box::new(c),
+            });
+            Some(&using)
+        }
+        JoinConstraint::None => None,
+        JoinConstraint::Natural => anyhow::bail!("NATURAL joins are not supported"),
+    };
+
+    let hash_keys = condition.and_then(|c| equi_join_columns(c, &eval, left_width));
+    let mut index: HashMap<String, Vec<usize>> = HashMap::new();
+    if let Some((_, right_key)) = hash_keys {
+        for (j, row) in right.rows.iter().enumerate() {
+            if let Some(key) = join_key(&row[right_key - left_width]) {
+                index.entry(key).or_default().push(j);
+            }
+        }
+    }
+    let all_rows = (0..right.rows.len()).collect::<Vec<_>>();
+
+    let mut rows = Vec::new();
+    let mut right_matched = vec![false; right.rows.len()];
+    for left_row in left.rows.iter() {
+        let candidates = match hash_keys {
+            Some((left_key, _)) => join_key(&left_row[left_key])
+                .and_then(|key| index.get(&key))
+                .map(|rows| rows.as_slice())
+                .unwrap_or_default(),
+            None => &all_rows,
+        };
+        let mut matched = false;
+        for &j in candidates {
+            let mut row = left_row.clone();
+            row.extend(right.rows[j].iter().cloned());
+            let keep = match condition {
+                Some(condition) => truthy(&eval.eval(condition, Context::Row(&row))?),
+                None => true,
+            };
+            if keep {
+                matched = true;
+                right_matched[j] = true;
+                rows.push(row);
+            }
+        }
+        if left_outer && !matched {
+            let mut row = left_row.clone();
+            row.resize(left_width + right_width, Value::Null);
+            rows.push(row);
+        }
+    }
+    if right_outer {
+        for (right_row, _) in right.rows.iter().zip(&right_matched).filter(|(_, m)| !**m) {
+            let mut row = vec![Value::Null; left_width];
+            row.extend(right_row.iter().cloned());
+            rows.push(row);
+        }
+    }
+    Ok(Relation {
+        columns,
+        rows: Rc::new(rows),
+    })
+}
+
+/// Column indexes of `left = right` when each side is a column of a different relation
+fn equi_join_columns(
+    condition: &Expr,
+    eval: &Evaluator,
+    left_width: usize,
+) -> Option<(usize, usize)> {
+    let Expr::BinaryOp {
+        left,
+        op: BinaryOperator::Eq,
+        right,
+    } = condition
+    else {
+        return None;
+    };
+    let a = eval.resolve_expr(left)?;
+    let b = eval.resolve_expr(right)?;
+    match (a < left_width, b < left_width) {
+        (true, false) => Some((a, b)),
+        (false, true) => Some((b, a)),
+        _ => None,
+    }
+}
+
+/// Key of a value in the hash join, NULL never matches
+fn join_key(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        v => Some(match as_number(v) {
+            Some(n) => n.to_string(),
+            None => cell_to_string(v),
+        }),
+    }
+}
+
+fn projections<'a>(select: &'a Select, relation: &Relation) -> Result<Vec<Projection<'a>>> {
+    let mut projections = Vec::new();
+    for item in &select.projection {
+        match item {
+            SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                projections.push(Projection::Expr(expr))
+            }
+            SelectItem::Wildcard(_) => {
+                projections.extend((0..relation.columns.len()).map(Projection::Column))
+            }
+            SelectItem::QualifiedWildcard(name, _) => {
+                projections.extend(qualified_columns(name, relation)?.map(Projection::Column))
+            }
+        }
+    }
+    Ok(projections)
+}
+
+/// Output names of the SELECT list. Columns of `*` that exist in several tables are
+/// qualified with the table name, remaining duplicates get a number
+fn projection_names(select: &Select, relation: &Relation) -> Result<Vec<String>> {
+    let wildcard_name = |i: usize| {
+        let column = &relation.columns[i];
+        let shared = relation
+            .columns
+            .iter()
+            .filter(|c| c.name == column.name)
+            .count()
+            > 1;
+        if shared {
+            format!("{}.{}", column.table, column.name)
+        } else {
+            column.name.clone()
+        }
+    };
+    let mut names = Vec::new();
+    for item in &select.projection {
+        match item {
+            SelectItem::UnnamedExpr(Expr::Identifier(ident)) => names.push(ident.value.clone()),
+            SelectItem::UnnamedExpr(Expr::CompoundIdentifier(idents)) => {
+                names.push(idents.last().map(|i| i.value.clone()).unwrap_or_default())
+            }
+            SelectItem::UnnamedExpr(expr) => names.push(expr.to_string()),
+            SelectItem::ExprWithAlias { alias, .. } => names.push(alias.value.clone()),
+            SelectItem::Wildcard(_) => names.extend((0..relation.columns.len()).map(wildcard_name)),
+            SelectItem::QualifiedWildcard(name, _) => {
+                names.extend(qualified_columns(name, relation)?.map(wildcard_name))
+            }
+        }
+    }
+    let mut unique: Vec<String> = Vec::new();
+    for name in names {
+        let mut candidate = name.clone();
+        let mut n = 1;
+        while unique.contains(&candidate) {
+            n += 1;
+            candidate = format!("{}_{}", name, n);
+        }
+        unique.push(candidate);
+    }
+    Ok(unique)
+}
+
+fn qualified_columns<'a>(
+    name: &'a sqlparser::ast::ObjectName,
+    relation: &'a Relation,
+) -> Result<impl Iterator<Item = usize> + 'a> {
+    let table = name.0.last().map(|i| i.value.as_str()).unwrap_or_default();
+    if !relation.columns.iter().any(|c| c.table == table) {
+        anyhow::bail!("Unknown table: {}", name);
+    }
+    Ok(relation
+        .columns
+        .iter()
+        .enumerate()
+        .filter(move |(_, c)| c.table == table)
+        .map(|(i, _)| i))
+}
+
+/// Value an output row is sorted by: a 1-based position in the SELECT list, an
+/// output column name, or any expression
+fn order_key(
+    expr: &Expr,
+    names: &[String],
+    values: &[Value],
+    eval: &Evaluator,
+    ctx: Context,
+) -> Result<Value> {
+    match expr {
+        Expr::Value(SqlValue::Number(n, _)) => {
+            let position = n
+                .parse::<usize>()
+                .ok()
+                .filter(|p| (1..=values.len()).contains(p))
+                .ok_or_else(|| anyhow::anyhow!("ORDER BY position {} is out of range", n))?;
+            Ok(values[position - 1].clone())
+        }
+        Expr::Identifier(ident) if names.contains(&ident.value) => {
+            let i = names
+                .iter()
+                .position(|n| n == &ident.value)
+                .unwrap_or_default();
+            Ok(values[i].clone())
+        }
+        expr => eval.eval(expr, ctx),
+    }
+}
+
+fn constant_count(expr: &Expr, clause: &str) -> Result<usize> {
+    let eval = Evaluator { columns: &[] };
+    let value = eval.eval(expr, Context::Row(&[]))?;
+    value
+        .as_u64()
+        .map(|n| n as usize)
+        .ok_or_else(|| anyhow::anyhow!("{} must be a non-negative integer", clause))
+}
+
+fn contains_aggregate(expr: &Expr) -> bool {
+    match expr {
+        Expr::Function(f) => {
+            is_aggregate(f)
+                || function_args(f).iter().any(|arg| match arg {
+                    FunctionArgExpr::Expr(e) => contains_aggregate(e),
+                    _ => false,
+                })
+        }
+        Expr::BinaryOp { left, right, .. } => contains_aggregate(left) || contains_aggregate(right),
+        Expr::UnaryOp { expr, .. }
+        | Expr::Nested(expr)
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr) => contains_aggregate(expr),
+        Expr::InList { expr, list, .. } => {
+            contains_aggregate(expr) || list.iter().any(contains_aggregate)
+        }
+        Expr::Between {
+            expr, low, high, ..
+        } => contains_aggregate(expr) || contains_aggregate(low) || contains_aggregate(high),
+        Expr::Like { expr, pattern, .. } | Expr::ILike { expr, pattern, .. } => {
+            contains_aggregate(expr) || contains_aggregate(pattern)
+        }
+        _ => false,
+    }
+}
+
+fn is_aggregate(f: &Function) -> bool {
+    AGGREGATES.contains(&function_name(f).as_str())
+}
+
+fn function_name(f: &Function) -> String {
+    f.name
+        .0
+        .last()
+        .map(|i| i.value.to_lowercase())
+        .unwrap_or_default()
+}
+
+fn function_args(f: &Function) -> Vec<&FunctionArgExpr> {
+    match &f.args {
+        FunctionArguments::List(list) => list
+            .args
+            .iter()
+            .map(|arg| match arg {
+                FunctionArg::Named { arg, .. }
+                | FunctionArg::ExprNamed { arg, .. }
+                | FunctionArg::Unnamed(arg) => arg,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Evaluate expressions against the rows of a relation
+struct Evaluator<'a> {
+    columns: &'a [Column],
+}
+
+impl Evaluator<'_> {
+    fn eval(&self, expr: &Expr, ctx: Context) -> Result<Value> {
+        match expr {
+            Expr::Identifier(ident) => Ok(self.column(self.resolve(None, ident)?, ctx)),
+            Expr::CompoundIdentifier(idents) => match idents.as_slice() {
+                [table, column] => Ok(self.column(self.resolve(Some(table), column)?, ctx)),
+                _ => anyhow::bail!("Unsupported column: {}", expr),
+            },
+            Expr::Value(value) => literal(value),
+            Expr::Nested(expr) => self.eval(expr, ctx),
+            Expr::UnaryOp { op, expr } => {
+                let value = self.eval(expr, ctx)?;
+                match op {
+                    UnaryOperator::Not if value.is_null() => Ok(Value::Null),
+                    UnaryOperator::Not => Ok(Value::Bool(!truthy(&value))),
+                    UnaryOperator::Minus => {
+                        Ok(as_number(&value).map(|n| number(-n)).unwrap_or(Value::Null))
+                    }
+                    UnaryOperator::Plus => Ok(value),
+                    op => anyhow::bail!("Unsupported operator: {}", op),
+                }
+            }
+            Expr::BinaryOp { left, op, right } => {
+                let left = self.eval(left, ctx)?;
+                let right = self.eval(right, ctx)?;
+                binary_op(&left, op, &right)
+            }
+            Expr::IsNull(expr) => Ok(Value::Bool(self.eval(expr, ctx)?.is_null())),
+            Expr::IsNotNull(expr) => Ok(Value::Bool(!self.eval(expr, ctx)?.is_null())),
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let value = self.eval(expr, ctx)?;
+                if value.is_null() {
+                    return Ok(Value::Null);
+                }
+                let mut found = false;
+                for item in list {
+                    if compare(&value, &self.eval(item, ctx)?) == Some(Ordering::Equal) {
+                        found = true;
+                        break;
+                    }
+                }
+                Ok(Value::Bool(found != *negated))
+            }
+            Expr::Between {
+                expr,
+                negated,
+                low,
+                high,
+            } => {
+                let value = self.eval(expr, ctx)?;
+                let low = compare(&value, &self.eval(low, ctx)?);
+                let high = compare(&value, &self.eval(high, ctx)?);
+                match (low, high) {
+                    (Some(low), Some(high)) => {
+                        Ok(Value::Bool((low.is_ge() && high.is_le()) != *negated))
+                    }
+                    _ => Ok(Value::Null),
+                }
+            }
+            Expr::Like {
+                negated,
+                expr,
+                pattern,
+                ..
+            } => self.like(expr, pattern, *negated, false, ctx),
+            Expr::ILike {
+                negated,
+                expr,
+                pattern,
+                ..
+            } => self.like(expr, pattern, *negated, true, ctx),
+            Expr::Function(f) if is_aggregate(f) => match ctx {
+                Context::Group(rows) => self.aggregate(f, rows),
+                Context::Row(_) => {
+                    anyhow::bail!("Aggregate function {} is not allowed here", f)
+                }
+            },
+            Expr::Function(f) => self.function(f, ctx),
+            Expr::Trim {
+                expr,
+                trim_where,
+                trim_what,
+                trim_characters: None,
+            } => {
+                let value = self.eval(expr, ctx)?;
+                let what = match trim_what {
+                    Some(what) => self.eval(what, ctx)?,
+                    None => Value::from(" "),
+                };
+                if value.is_null() || what.is_null() {
+                    return Ok(Value::Null);
+                }
+                let (text, what) = (cell_to_string(&value), cell_to_string(&what));
+                let trimmed = |c: char| what.contains(c);
+                Ok(Value::from(match trim_where {
+                    Some(TrimWhereField::Leading) => text.trim_start_matches(trimmed),
+                    Some(TrimWhereField::Trailing) => text.trim_end_matches(trimmed),
+                    _ => text.trim_matches(trimmed),
+                }))
+            }
+            expr => anyhow::bail!("Unsupported expression: {}", expr),
+        }
+    }
+
+    /// Value of a column, the first row stands for a group
+    fn column(&self, i: usize, ctx: Context) -> Value {
+        let row = match ctx {
+            Context::Row(row) => Some(row),
+            Context::Group(rows) => rows.first().copied(),
+        };
+        row.and_then(|r| r.get(i)).cloned().unwrap_or(Value::Null)
+    }
+
+    /// Index of a column, unquoted names also match case-insensitively
+    fn resolve(&self, table: Option<&Ident>, column: &Ident) -> Result<usize> {
+        let table_matches = |c: &Column| table.is_none_or(|t| ident_matches(t, &c.table));
+        let mut matches = self
+            .columns
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| table_matches(c) && c.name == column.value)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        if matches.is_empty() {
+            matches = self
+                .columns
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| table_matches(c) && ident_matches(column, &c.name))
+                .map(|(i, _)| i)
+                .collect();
+        }
+        let name = match table {
+            Some(table) => format!("{}.{}", table, column),
+            None => column.to_string(),
+        };
+        match matches.as_slice() {
+            [i] => Ok(*i),
+            [] => anyhow::bail!("Unknown column: {}", name),
+            _ => anyhow::bail!("Ambiguous column: {}, qualify it with the table name", name),
+        }
+    }
+
+    fn resolve_expr(&self, expr: &Expr) -> Option<usize> {
+        match expr {
+            Expr::Identifier(ident) => self.resolve(None, ident).ok(),
+            Expr::CompoundIdentifier(idents) => match idents.as_slice() {
+                [table, column] => self.resolve(Some(table), column).ok(),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn like(
+        &self,
+        expr: &Expr,
+        pattern: &Expr,
+        negated: bool,
+        ignore_case: bool,
+        ctx: Context,
+    ) -> Result<Value> {
+        let value = self.eval(expr, ctx)?;
+        let pattern = self.eval(pattern, ctx)?;
+        if value.is_null() || pattern.is_null() {
+            return Ok(Value::Null);
+        }
+        let regex = like_regex(&cell_to_string(&pattern), ignore_case)?;
+        Ok(Value::Bool(
+            regex.is_match(&cell_to_string(&value)) != negated,
+        ))
+    }
+
+    fn aggregate(&self, f: &Function, rows: &[&[Value]]) -> Result<Value> {
+        let name = function_name(f);
+        let distinct = matches!(
+            &f.args,
+            FunctionArguments::List(list) if list.duplicate_treatment == Some(DuplicateTreatment::Distinct)
+        );
+        let args = function_args(f);
+        let arg = match args.as_slice() {
+            [FunctionArgExpr::Wildcard] if name == "count" => return Ok(Value::from(rows.len())),
+            [FunctionArgExpr::Expr(expr)] => expr,
+            _ => anyhow::bail!("{} takes a single argument", name),
+        };
+        let mut values = rows
+            .iter()
+            .map(|row| self.eval(arg, Context::Row(row)))
+            .filter(|v| !matches!(v, Ok(Value::Null)))
+            .collect::<Result<Vec<_>>>()?;
+        if distinct {
+            let mut seen = std::collections::HashSet::new();
+            values.retain(|v| seen.insert(v.to_string()));
+        }
+        let numbers = || values.iter().filter_map(as_number);
+        Ok(match name.as_str() {
+            "count" => Value::from(values.len()),
+            "sum" if values.is_empty() => Value::Null,
+            "sum" => number(numbers().sum()),
+            "avg" => {
+                let (sum, count) = numbers().fold((0.0, 0), |(s, c), n| (s + n, c + 1));
+                if count == 0 {
+                    Value::Null
+                } else {
+                    Value::from(sum / count as f64)
+                }
+            }
+            "min" => extreme(values, Ordering::Less),
+            _ => extreme(values, Ordering::Greater),
+        })
+    }
+
+    fn function(&self, f: &Function, ctx: Context) -> Result<Value> {
+        let name = function_name(f);
+        let args = function_args(f)
+            .into_iter()
+            .map(|arg| match arg {
+                FunctionArgExpr::Expr(expr) => self.eval(expr, ctx),
+                _ => anyhow::bail!("Unexpected argument in {}", f),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let text = |v: &Value| (!v.is_null()).then(|| cell_to_string(v));
+        Ok(match (name.as_str(), args.as_slice()) {
+            ("lower", [v]) => text(v)
+                .map(|s| Value::from(s.to_lowercase()))
+                .unwrap_or_default(),
+            ("upper", [v]) => text(v)
+                .map(|s| Value::from(s.to_uppercase()))
+                .unwrap_or_default(),
+            ("length", [v]) => text(v)
+                .map(|s| Value::from(s.chars().count()))
+                .unwrap_or_default(),
+            ("abs", [v]) => as_number(v).map(|n| number(n.abs())).unwrap_or_default(),
+            ("round", [v]) => as_number(v).map(|n| number(n.round())).unwrap_or_default(),
+            ("round", [v, digits]) => match (as_number(v), as_number(digits)) {
+                (Some(n), Some(d)) => {
+                    let scale = 10f64.powi(d as i32);
+                    number((n * scale).round() / scale)
+                }
+                _ => Value::Null,
+            },
+            ("coalesce", args) => args
+                .iter()
+                .find(|v| !v.is_null())
+                .cloned()
+                .unwrap_or_default(),
+            _ => anyhow::bail!("Unsupported function: {}", f),
+        })
+    }
+}
+
+fn ident_matches(ident: &Ident, name: &str) -> bool {
+    match ident.quote_style {
+        Some(_) => ident.value == name,
+        None => ident.value.eq_ignore_ascii_case(name),
+    }
+}
+
+fn literal(value: &SqlValue) -> Result<Value> {
+    Ok(match value {
+        SqlValue::Number(n, _) => match n.parse::<i64>() {
+            Ok(i) => Value::from(i),
+            Err(_) => Value::from(n.parse::<f64>()?),
+        },
+        SqlValue::SingleQuotedString(s) | SqlValue::DoubleQuotedString(s) => {
+            Value::from(s.as_str())
+        }
+        SqlValue::Boolean(b) => Value::Bool(*b),
+        SqlValue::Null => Value::Null,
+        value => anyhow::bail!("Unsupported value: {}", value),
+    })
+}
+
+fn binary_op(left: &Value, op: &BinaryOperator, right: &Value) -> Result<Value> {
+    // three-valued logic: NULL AND false is false, NULL OR true is true
+    match op {
+        BinaryOperator::And => {
+            return Ok(match (left, right) {
+                (l, r) if (!l.is_null() && !truthy(l)) || (!r.is_null() && !truthy(r)) => {
+                    Value::Bool(false)
+                }
+                (l, r) if l.is_null() || r.is_null() => Value::Null,
+                _ => Value::Bool(true),
+            })
+        }
+        BinaryOperator::Or => {
+            return Ok(match (left, right) {
+                (l, r) if truthy(l) || truthy(r) => Value::Bool(true),
+                (l, r) if l.is_null() || r.is_null() => Value::Null,
+                _ => Value::Bool(false),
+            })
+        }
+        _ => {}
+    }
+    if left.is_null() || right.is_null() {
+        return Ok(Value::Null);
+    }
+    let cmp = |f: fn(Ordering) -> bool| compare(left, right).map(f).map(Value::Bool);
+    let arithmetic = |f: fn(f64, f64) -> f64| match (as_number(left), as_number(right)) {
+        (Some(l), Some(r)) => number(f(l, r)),
+        _ => Value::Null,
+    };
+    Ok(match op {
+        BinaryOperator::Eq => cmp(Ordering::is_eq).unwrap_or_default(),
+        BinaryOperator::NotEq => cmp(Ordering::is_ne).unwrap_or_default(),
+        BinaryOperator::Lt => cmp(Ordering::is_lt).unwrap_or_default(),
+        BinaryOperator::LtEq => cmp(Ordering::is_le).unwrap_or_default(),
+        BinaryOperator::Gt => cmp(Ordering::is_gt).unwrap_or_default(),
+        BinaryOperator::GtEq => cmp(Ordering::is_ge).unwrap_or_default(),
+        BinaryOperator::Plus => arithmetic(|l, r| l + r),
+        BinaryOperator::Minus => arithmetic(|l, r| l - r),
+        BinaryOperator::Multiply => arithmetic(|l, r| l * r),
+        BinaryOperator::Divide if as_number(right) == Some(0.0) => Value::Null,
+        BinaryOperator::Divide => arithmetic(|l, r| l / r),
+        BinaryOperator::Modulo if as_number(right) == Some(0.0) => Value::Null,
+        BinaryOperator::Modulo => arithmetic(|l, r| l % r),
+        BinaryOperator::StringConcat => {
+            Value::from(format!("{}{}", cell_to_string(left), cell_to_string(right)))
+        }
+        op => anyhow::bail!("Unsupported operator: {}", op),
+    })
+}
+
+/// Compare two values, numerically when both are numbers. NULL is not comparable
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Null, _) | (_, Value::Null) => None,
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        // a number column compared with a quoted literal is still compared as numbers
+        _ if a.is_number() || b.is_number() => match (as_number(a), as_number(b)) {
+            (Some(x), Some(y)) => x.partial_cmp(&y),
+            _ => Some(cell_to_string(a).cmp(&cell_to_string(b))),
+        },
+        _ => Some(cell_to_string(a).cmp(&cell_to_string(b))),
+    }
+}
+
+fn extreme(values: Vec<Value>, keep: Ordering) -> Value {
+    values
+        .into_iter()
+        .reduce(|a, b| if compare(&b, &a) == Some(keep) { b } else { a })
+        .unwrap_or_default()
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Numbers without a fractional part are written as integers
+fn number(n: f64) -> Value {
+    if n.fract() == 0.0 && n.abs() < i64::MAX as f64 {
+        Value::from(n as i64)
+    } else {
+        Value::from(n)
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0),
+        _ => false,
+    }
+}
+
+/// Translate a LIKE pattern, `%` matches any text and `_` a single character
+fn like_regex(pattern: &str, ignore_case: bool) -> Result<Regex> {
+    let mut regex = String::from(if ignore_case { "(?is)^" } else { "(?s)^" });
+    for c in pattern.chars() {
+        match c {
+            '%' => regex.push_str(".*"),
+            '_' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Ok(Regex::new(&regex)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn query(sql: &str) -> Result<Vec<Value>> {
+        let sources = [QuerySource {
+            name: None,
+            path: "assets/juventus.csv",
+        }];
+        Ok(query_records(sql, &sources, &Default::default())?.1)
+    }
+
+    #[test]
+    fn test_query_group_by() -> Result<()> {
+        let rows = query(
+            "SELECT Position, count(*) FROM juventus GROUP BY Position ORDER BY 2 DESC, 1 LIMIT 2",
+        )?;
+        assert_eq!(
+            rows,
+            vec![
+                json!({"Position": "Central Midfield", "count(*)": 6}),
+                json!({"Position": "Centre-Back", "count(*)": 5}),
+            ]
+        );
+        let rows = query(
+            r#"SELECT max("Kit Number") AS max, sum("Kit Number") total FROM juventus
+               WHERE Nationality IN ('Italy', 'France') AND NOT Position LIKE '%Back'"#,
+        )?;
+        assert_eq!(rows, vec![json!({"max": 77, "total": 217})]);
+        assert!(query("SELECT Name FROM juventus WHERE count(*) > 1").is_err());
+        assert!(query("SELECT Club FROM juventus").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_join() -> Result<()> {
+        let sources = [
+            QuerySource {
+                name: Some("a"),
+                path: "assets/juventus.csv",
+            },
+            QuerySource {
+                name: Some("b"),
+                path: "assets/juventus.csv",
+            },
+        ];
+        let sql = r#"SELECT a.Name, b.Name AS next FROM a
+            LEFT JOIN b ON b."Kit Number" = a."Kit Number" + 1
+            WHERE a."Kit Number" IN (1, 2, 77) ORDER BY a."Kit Number""#;
+        let (_, rows) = query_records(sql, &sources, &Default::default())?;
+        assert_eq!(
+            rows,
+            vec![
+                json!({"Name": "Wojciech Szczesny", "next": "Mattia De Sciglio"}),
+                json!({"Name": "Mattia De Sciglio", "next": "Giorgio Chiellini"}),
+                json!({"Name": "Gianluigi Buffon", "next": null}),
+            ]
+        );
+        Ok(())
+    }
+
+    /// Query `players` and `teams`, two small tables with NULLs
+    fn query_players(sql: &str) -> Result<Vec<Value>> {
+        let dir = tempfile::tempdir()?;
+        let players = dir.path().join("players.csv");
+        let teams = dir.path().join("teams.csv");
+        std::fs::write(
+            &players,
+            "id,name,team,kit,goals\n1,Buffon,JUV,1,0\n2,Perin,JUV,37,\n3,Pjanic,BAR,5,12\n\
+             4,Kean,,18,7\n5,Dybala,JUV,10,30\n",
+        )?;
+        std::fs::write(&teams, "team,city\nJUV,Turin\nBAR,Barcelona\nPSG,Paris\n")?;
+        let sources = [players, teams].map(|p| p.to_string_lossy().into_owned());
+        let sources = sources
+            .iter()
+            .map(|path| QuerySource { name: None, path })
+            .collect::<Vec<_>>();
+        Ok(query_records(sql, &sources, &Default::default())?.1)
+    }
+
+    #[test]
+    fn test_query_distinct() -> Result<()> {
+        let rows = query_players("SELECT DISTINCT team FROM players ORDER BY team")?;
+        assert_eq!(
+            rows,
+            vec![
+                json!({"team": "BAR"}),
+                json!({"team": "JUV"}),
+                json!({"team": null})
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_limit_offset() -> Result<()> {
+        let rows = query_players("SELECT name FROM players ORDER BY id LIMIT 2 OFFSET 1")?;
+        assert_eq!(
+            rows,
+            vec![json!({"name": "Perin"}), json!({"name": "Pjanic"})]
+        );
+        assert!(query_players("SELECT name FROM players LIMIT -1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_having() -> Result<()> {
+        let rows = query_players(
+            "SELECT team, count(*) AS n FROM players GROUP BY team HAVING count(*) > 1",
+        )?;
+        assert_eq!(rows, vec![json!({"team": "JUV", "n": 3})]);
+        assert!(query_players("SELECT name FROM players HAVING kit > 1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_aggregates() -> Result<()> {
+        let rows = query_players(
+            "SELECT count(goals) AS c, count(DISTINCT team) AS t, avg(goals) AS a,
+                min(name) AS mn, max(kit) AS mx, sum(goals) AS s FROM players",
+        )?;
+        assert_eq!(
+            rows,
+            vec![json!({"c": 4, "t": 2, "a": 12.25, "mn": "Buffon", "mx": 37, "s": 49})]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_right_join() -> Result<()> {
+        let rows = query_players(
+            "SELECT p.name, t.city FROM players p RIGHT JOIN teams t ON p.team = t.team
+             ORDER BY t.city, p.name",
+        )?;
+        assert_eq!(
+            rows,
+            vec![
+                json!({"name": "Pjanic", "city": "Barcelona"}),
+                json!({"name": null, "city": "Paris"}),
+                json!({"name": "Buffon", "city": "Turin"}),
+                json!({"name": "Dybala", "city": "Turin"}),
+                json!({"name": "Perin", "city": "Turin"}),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_full_join() -> Result<()> {
+        let rows = query_players(
+            "SELECT p.name, t.city FROM players p FULL JOIN teams t ON p.team = t.team
+             WHERE p.name IS NULL OR t.city IS NULL",
+        )?;
+        assert_eq!(
+            rows,
+            vec![
+                json!({"name": "Kean", "city": null}),
+                json!({"name": null, "city": "Paris"}),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_join_using() -> Result<()> {
+        let rows = query_players(
+            "SELECT name, city FROM players JOIN teams USING (team) WHERE kit < 10 ORDER BY name",
+        )?;
+        assert_eq!(
+            rows,
+            vec![
+                json!({"name": "Buffon", "city": "Turin"}),
+                json!({"name": "Pjanic", "city": "Barcelona"}),
+            ]
+        );
+        assert!(query_players("SELECT * FROM players JOIN teams USING (city)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_cross_join() -> Result<()> {
+        let rows = query_players("SELECT count(*) AS n FROM players, teams")?;
+        assert_eq!(rows, vec![json!({"n": 15})]);
+        let rows = query_players("SELECT count(*) AS n FROM players CROSS JOIN teams")?;
+        assert_eq!(rows, vec![json!({"n": 15})]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_like() -> Result<()> {
+        let names = |sql: &str| -> Result<Vec<Value>> {
+            Ok(query_players(sql)?
+                .into_iter()
+                .map(|r| r["name"].clone())
+                .collect())
+        };
+        assert_eq!(
+            names("SELECT name FROM players WHERE name LIKE 'P%' ORDER BY name")?,
+            ["Perin", "Pjanic"]
+        );
+        assert_eq!(
+            names("SELECT name FROM players WHERE name ILIKE 'p_anic'")?,
+            ["Pjanic"]
+        );
+        assert_eq!(
+            names("SELECT name FROM players WHERE name NOT LIKE '%n' ORDER BY name")?,
+            ["Dybala", "Pjanic"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_predicates() -> Result<()> {
+        let rows =
+            query_players("SELECT name FROM players WHERE kit BETWEEN 5 AND 18 ORDER BY kit")?;
+        assert_eq!(
+            rows,
+            vec![
+                json!({"name": "Pjanic"}),
+                json!({"name": "Dybala"}),
+                json!({"name": "Kean"}),
+            ]
+        );
+        let rows = query_players("SELECT name FROM players WHERE goals IS NULL")?;
+        assert_eq!(rows, vec![json!({"name": "Perin"})]);
+        let rows = query_players("SELECT name FROM players WHERE team NOT IN ('JUV')")?;
+        assert_eq!(rows, vec![json!({"name": "Pjanic"})]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_functions() -> Result<()> {
+        let rows = query_players(
+            "SELECT lower(name) AS l, upper('juv') AS u, length(name) AS len, abs(-kit) AS a,
+                round(kit / 3.0, 2) AS r, round(2.5) AS r0, coalesce(goals, 0) AS g,
+                trim('  x ') AS t, trim(LEADING '-' FROM '--y-') AS tl, name || '!' AS e, kit % 10 AS m, kit / 0 AS d
+             FROM players WHERE id = 2",
+        )?;
+        assert_eq!(
+            rows,
+            vec![json!({
+                "l": "perin", "u": "JUV", "len": 5, "a": 37, "r": 12.33, "r0": 3, "g": 0,
+                "t": "x", "tl": "y-", "e": "Perin!", "m": 7, "d": null
+            })]
+        );
+        assert!(query_players("SELECT reverse(name) FROM players").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_empty_csv() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let output = dir.path().join("empty.csv");
+        let output = output.to_str().unwrap();
+        let sources = [QuerySource {
+            name: None,
+            path: "assets/juventus.csv",
+        }];
+        process_csv_query(
+            "SELECT Name FROM juventus WHERE Name = 'zzz'",
+            &sources,
+            output,
+            OutputFormat::Csv,
+            &Default::default(),
+            &Default::default(),
+        )?;
+        assert_eq!(std::fs::read_to_string(output)?, "Name\n");
+        Ok(())
+    }
+}
@@ -0,0 +1,451 @@
+use anyhow::Result;
+use csv::StringRecord;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Number};
+use std::{fmt::Display, fs, io::Write, path::Path};
+
+use crate::{
+    csv_headers, csv_input, csv_stats, get_data_writer, header_line, ColumnType, CsvReaderOpts,
+    CsvTypeOpts, FakeKind, OutputFormat, ReportFormat,
+};
+
+/// Columns expected in a CSV file, read from a YAML, TOML or JSON document
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CsvSchema {
+    /// Reject columns that are not declared
+    #[serde(default)]
+    pub strict: bool,
+    /// Extra chrono formats accepted for date columns
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub date_formats: Vec<String>,
+    pub columns: Vec<ColumnSchema>,
+}
+
+/// Rules of a single column, empty cells are only checked for `required`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnSchema {
+    pub name: String,
+    #[serde(rename = "type", default = "default_type")]
+    pub column_type: ColumnType,
+    /// The column must exist and every cell must have a value
+    #[serde(default)]
+    pub required: bool,
+    /// Regex the whole value must match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// Allowed values
+    #[serde(rename = "enum", default, skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<Number>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<Number>,
+    /// Generator used by `csv fake`, guessed from the name and type if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fake: Option<FakeKind>,
+}
+
+fn default_type() -> ColumnType {
+    ColumnType::String
+}
+
+/// A value or a header breaking the schema
+#[derive(Debug, Clone, Serialize)]
+pub struct Violation {
+    /// Absent for a header that is not read from the file, see `header_line`
+    pub line: Option<u64>,
+    /// 1-based column number, absent for a missing column
+    pub column: Option<usize>,
+    pub column_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    pub message: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {}, ", line)?;
+        }
+        match self.column {
+            Some(column) => write!(f, "column {} ({})", column, self.column_name)?,
+            None => write!(f, "column {}", self.column_name)?,
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl CsvSchema {
+    /// Load a schema, the format comes from the file extension
+    pub fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let extension = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        let schema = match extension.as_str() {
+            "yaml" | "yml" => serde_yaml::from_str(&content)?,
+            "toml" => toml::from_str(&content)?,
+            "json" => serde_json::from_str(&content)?,
+            _ => anyhow::bail!("Schema must be a .yaml, .toml or .json file: {}", path),
+        };
+        Ok(schema)
+    }
+}
+
+/// A schema bound to the columns of a file
+pub struct SchemaValidator {
+    columns: Vec<ColumnRule>,
+    type_opts: CsvTypeOpts,
+}
+
+struct ColumnRule {
+    schema: ColumnSchema,
+    index: usize,
+    pattern: Option<Regex>,
+}
+
+impl SchemaValidator {
+    /// Match the schema columns with the header, returns the violations of the header
+    pub fn new(
+        schema: &CsvSchema,
+        headers: &[String],
+        header_line: Option<u64>,
+    ) -> Result<(Self, Vec<Violation>)> {
+        let mut violations = Vec::new();
+        let mut columns = Vec::new();
+        for column in &schema.columns {
+            let pattern = column
+                .pattern
+                .as_ref()
+                .map(|p| Regex::new(&format!("^(?:{})$", p)))
+                .transpose()
+                .map_err(|e| {
+                    anyhow::anyhow!("Invalid pattern of column {:?}: {}", column.name, e)
+                })?;
+            match headers.iter().position(|h| h == &column.name) {
+                Some(index) => columns.push(ColumnRule {
+                    schema: column.clone(),
+                    index,
+                    pattern,
+                }),
+                None if column.required => violations.push(Violation {
+                    line: header_line,
+                    column: None,
+                    column_name: column.name.clone(),
+                    value: None,
+                    message: "required column is missing".to_string(),
+                }),
+                None => {}
+            }
+        }
+        if schema.strict {
+            for (i, header) in headers.iter().enumerate() {
+                if !schema.columns.iter().any(|c| &c.name == header) {
+                    violations.push(Violation {
+                        line: header_line,
+                        column: Some(i + 1),
+                        column_name: header.clone(),
+                        value: None,
+                        message: "column is not in the schema".to_string(),
+                    });
+                }
+            }
+        }
+        let type_opts = CsvTypeOpts {
+            infer_dates: true,
+            date_formats: schema.date_formats.clone(),
+            ..Default::default()
+        };
+        Ok((Self { columns, type_opts }, violations))
+    }
+
+    /// Column types declared by the schema, to convert the records with
+    pub fn column_types(&self) -> impl Iterator<Item = (&str, ColumnType)> {
+        self.columns
+            .iter()
+            .map(|c| (c.schema.name.as_str(), c.schema.column_type))
+    }
+
+    /// Check every declared column of a record
+    pub fn validate(&self, record: &StringRecord) -> Vec<Violation> {
+        let line = record.position().map(|p| p.line());
+        self.columns
+            .iter()
+            .filter_map(|rule| {
+                let value = record.get(rule.index).unwrap_or_default();
+                let message = self.check(rule, value)?;
+                Some(Violation {
+                    line,
+                    column: Some(rule.index + 1),
+                    column_name: rule.schema.name.clone(),
+                    value: Some(value.to_string()),
+                    message,
+                })
+            })
+            .collect()
+    }
+
+    fn check(&self, rule: &ColumnRule, value: &str) -> Option<String> {
+        let column = &rule.schema;
+        if value.trim().is_empty() {
+            return column
+                .required
+                .then(|| "required value is missing".to_string());
+        }
+        if column.column_type != ColumnType::String
+            && column
+                .column_type
+                .parse_value(value, &self.type_opts)
+                .is_err()
+        {
+            return Some(format!("{:?} is not a valid {}", value, column.column_type));
+        }
+        if let Some(values) = &column.values {
+            if !values.iter().any(|v| v == value) {
+                return Some(format!("{:?} is not one of {}", value, values.join(", ")));
+            }
+        }
+        if let Some(pattern) = &rule.pattern {
+            if !pattern.is_match(value) {
+                return Some(format!("{:?} does not match {}", value, pattern.as_str()));
+            }
+        }
+        if column.min.is_some() || column.max.is_some() {
+            let Ok(n) = value.trim().parse::<f64>() else {
+                return Some(format!("{:?} is not a number", value));
+            };
+            if let Some(min) = column
+                .min
+                .as_ref()
+                .filter(|m| m.as_f64().is_some_and(|m| n < m))
+            {
+                return Some(format!("{} is less than the minimum {}", value.trim(), min));
+            }
+            if let Some(max) = column
+                .max
+                .as_ref()
+                .filter(|m| m.as_f64().is_some_and(|m| n > m))
+            {
+                return Some(format!(
+                    "{} is greater than the maximum {}",
+                    value.trim(),
+                    max
+                ));
+            }
+        }
+        None
+    }
+}
+
+/// Check a CSV file against a schema and report every violation. Fails if any is found
+pub fn process_csv_validate(
+    input: &str,
+    schema: &str,
+    output: &str,
+    format: ReportFormat,
+    opts: &CsvReaderOpts,
+) -> Result<()> {
+    let schema = CsvSchema::load(schema)?;
+    let (rows, violations) = validate_csv(input, &schema, opts)?;
+    let mut writer = get_data_writer(output)?;
+    match format {
+        ReportFormat::Json => {
+            let report = json!({"rows": rows, "violations": violations});
+            serde_json::to_writer_pretty(&mut writer, &report)?;
+            writeln!(writer)?;
+        }
+        ReportFormat::Text => {
+            for violation in &violations {
+                writeln!(writer, "{}", violation)?;
+            }
+            writeln!(
+                writer,
+                "{} rows checked, {} violations",
+                rows,
+                violations.len()
+            )?;
+        }
+    }
+    writer.finish()?;
+    if !violations.is_empty() {
+        anyhow::bail!("{} does not match the schema", input);
+    }
+    Ok(())
+}
+
+/// Number of records and every violation of the schema
+pub fn validate_csv(
+    input: &str,
+    schema: &CsvSchema,
+    opts: &CsvReaderOpts,
+) -> Result<(usize, Vec<Violation>)> {
+    let mut reader = csv_input(input, opts)?;
+    let headers = csv_headers(&mut reader, opts)?;
+    let header_line = header_line(&mut reader, opts)?;
+    let (validator, mut violations) = SchemaValidator::new(schema, &headers, header_line)?;
+    let mut rows = 0;
+    for record in reader.records() {
+        violations.extend(validator.validate(&record?));
+        rows += 1;
+    }
+    Ok((rows, violations))
+}
+
+/// Infer a schema from a CSV file and write it as yaml, toml or json
+pub fn process_csv_schema_infer(
+    input: &str,
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    max_enum: usize,
+) -> Result<()> {
+    let schema = infer_schema(input, opts, max_enum)?;
+    let content = match format {
+        OutputFormat::Yaml => serde_yaml::to_string(&schema)?,
+        OutputFormat::Json => serde_json::to_string_pretty(&schema)? + "\n",
+        OutputFormat::Toml => toml::to_string(&schema)?,
+        _ => anyhow::bail!(
+            "Schema can be written as yaml, toml or json, not {}",
+            format
+        ),
+    };
+    let mut writer = get_data_writer(output)?;
+    writer.write_all(content.as_bytes())?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Build a schema from the profile of every column. Columns without empty cells are
+/// required, numeric columns get their range and text columns with at most
+/// `max_enum` repeated values get them as an enum
+pub fn infer_schema(input: &str, opts: &CsvReaderOpts, max_enum: usize) -> Result<CsvSchema> {
+    let stats = csv_stats(input, opts, max_enum, false)?;
+    let columns = stats
+        .into_iter()
+        .map(|column| {
+            let column_type = column["type"]
+                .as_str()
+                .and_then(|t| t.parse().ok())
+                .filter(|t| *t != ColumnType::Null)
+                .unwrap_or(ColumnType::String);
+            let count = column["count"].as_u64().unwrap_or_default();
+            let distinct = column["distinct"].as_u64().unwrap_or_default();
+            let numeric = matches!(column_type, ColumnType::Int | ColumnType::Float);
+            let bound = |key: &str| match &column[key] {
+                serde_json::Value::Number(n) if numeric => Some(n.clone()),
+                _ => None,
+            };
+            let values = (column_type == ColumnType::String
+                && distinct > 0
+                && distinct as usize <= max_enum
+                && distinct < count)
+                .then(|| {
+                    let mut values = column["top"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|t| t["value"].as_str().map(|v| v.to_string()))
+                        .collect::<Vec<_>>();
+                    values.sort();
+                    values
+                });
+            ColumnSchema {
+                name: column["column"].as_str().unwrap_or_default().to_string(),
+                column_type,
+                required: count > 0 && column["nulls"] == 0,
+                pattern: None,
+                values,
+                min: bound("min"),
+                max: bound("max"),
+                fake: None,
+            }
+        })
+        .collect();
+    Ok(CsvSchema {
+        strict: false,
+        date_formats: Vec::new(),
+        columns,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_csv() -> Result<()> {
+        let schema = CsvSchema::load("assets/juventus.schema.yaml")?;
+        let (rows, violations) = validate_csv("assets/juventus.csv", &schema, &Default::default())?;
+        assert_eq!(rows, 27);
+        assert!(violations.is_empty());
+
+        let mut file = tempfile::NamedTempFile::new()?;
+        file.write_all(
+            b"Name,Position,DOB,Kit Number,Club\n\
+              ,Goalkeeper,\"Apr 18, 1990 (29)\",1,Juventus\n\
+              Perin,Keeper,1992-11-10,100,Juventus\n",
+        )?;
+        let schema = CsvSchema {
+            strict: true,
+            ..schema
+        };
+        let (_, violations) =
+            validate_csv(file.path().to_str().unwrap(), &schema, &Default::default())?;
+        let messages = violations.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            vec![
+                "line 1, column Nationality: required column is missing",
+                "line 1, column 5 (Club): column is not in the schema",
+                "line 2, column 1 (Name): required value is missing",
+                "line 3, column 2 (Position): \"Keeper\" is not one of Goalkeeper, Centre-Back, \
+                 Left-Back, Right-Back, Defensive Midfield, Central Midfield, Left Winger, \
+                 Right Winger, Second Striker, Centre-Forward",
+                "line 3, column 3 (DOB): \"1992-11-10\" does not match \
+                 ^(?:[A-Z][a-z]{2} \\d{1,2}, \\d{4} \\(\\d+\\))$",
+                "line 3, column 4 (Kit Number): 100 is greater than the maximum 99",
+            ]
+        );
+
+        // without a header row the column names are not on any line
+        let columns = ["Name", "Position", "DOB", "Kit Number", "Club"];
+        let opts = CsvReaderOpts {
+            header: false,
+            columns: Some(columns.iter().map(|c| c.to_string()).collect()),
+            ..Default::default()
+        };
+        let (_, violations) = validate_csv(file.path().to_str().unwrap(), &schema, &opts)?;
+        assert_eq!(
+            violations[0].to_string(),
+            "column Nationality: required column is missing"
+        );
+        assert_eq!(violations.iter().filter_map(|v| v.line).max(), Some(3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_infer_schema() -> Result<()> {
+        let schema = infer_schema("assets/juventus.csv", &Default::default(), 10)?;
+        let position = &schema.columns[1];
+        assert!(position.required);
+        assert_eq!(position.values.as_ref().map(|v| v.len()), Some(10));
+        let kit = &schema.columns[4];
+        assert_eq!(kit.column_type, ColumnType::Int);
+        assert_eq!(
+            (kit.min.clone(), kit.max.clone()),
+            (Some(1.into()), Some(77.into()))
+        );
+        assert!(schema.columns[0].values.is_none());
+
+        // the inferred schema survives a round trip and accepts its own file
+        let schema: CsvSchema = serde_yaml::from_str(&serde_yaml::to_string(&schema)?)?;
+        let (_, violations) = validate_csv("assets/juventus.csv", &schema, &Default::default())?;
+        assert!(violations.is_empty());
+        Ok(())
+    }
+}
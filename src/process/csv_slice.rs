@@ -0,0 +1,398 @@
+use anyhow::Result;
+use csv::StringRecord;
+use encoding_rs::UTF_8;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::{
+    csv_headers, csv_input, csv_reader, detect_encoding, output_writer, record_to_value,
+    string_values, Compression, CsvEncoding, CsvReaderOpts, CsvWriterOpts, OutputFormat,
+};
+
+/// Bytes read from the end of the file before `tail` widens its window
+const TAIL_WINDOW: u64 = 64 * 1024;
+/// Records allocated up front by `tail` and `sample`, the rest as they come
+const MAX_PREALLOCATED: usize = 1024;
+
+/// Write the first `n` records, the rest of the input is never read
+pub fn process_csv_head(
+    input: &str,
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    n: usize,
+    writer_opts: &CsvWriterOpts,
+) -> Result<()> {
+    let mut reader = csv_input(input, opts)?;
+    let headers = csv_headers(&mut reader, opts)?;
+    let mut writer = output_writer(output, format, writer_opts)?;
+    writer.write_headers(&headers)?;
+    for record in reader.records().take(n) {
+        writer.write_record(&record_to_value(&headers, string_values(&record?)))?;
+    }
+    writer.finish()
+}
+
+/// Write the last `n` records
+pub fn process_csv_tail(
+    input: &str,
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    n: usize,
+    writer_opts: &CsvWriterOpts,
+) -> Result<()> {
+    let (headers, records) = tail_records(input, opts, n)?;
+    write_records(output, format, writer_opts, &headers, records)
+}
+
+/// Write `n` records picked at random in a single pass, in the order of the input.
+/// The same seed always picks the same records
+pub fn process_csv_sample(
+    input: &str,
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    n: usize,
+    seed: Option<u64>,
+    writer_opts: &CsvWriterOpts,
+) -> Result<()> {
+    let mut reader = csv_input(input, opts)?;
+    let headers = csv_headers(&mut reader, opts)?;
+    let mut rng = match seed {
+        Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+        None => ChaCha8Rng::from_entropy(),
+    };
+    let records = sample_records(reader.records(), n, &mut rng)?;
+    write_records(output, format, writer_opts, &headers, records)
+}
+
+/// The last `n` records and the header. Plain UTF-8 files are read backwards from
+/// the end, anything else (stdin, compressed or transcoded input) is scanned once
+pub fn tail_records(
+    input: &str,
+    opts: &CsvReaderOpts,
+    n: usize,
+) -> Result<(Vec<String>, Vec<StringRecord>)> {
+    if let Some(data_start) = seekable_data_start(input, opts)? {
+        let headers = csv_headers(&mut csv_input(input, opts)?, opts)?;
+        let mut file = File::open(input)?;
+        let len = file.metadata()?.len();
+        let width = headers.len();
+        if let Some(records) = tail_from_end(&mut file, len, opts, data_start, n, width)? {
+            return Ok((headers, records));
+        }
+    }
+    let mut reader = csv_input(input, opts)?;
+    let headers = csv_headers(&mut reader, opts)?;
+    let mut last = VecDeque::with_capacity(n.min(MAX_PREALLOCATED));
+    for record in reader.records() {
+        if n == 0 {
+            break;
+        }
+        if last.len() == n {
+            last.pop_front();
+        }
+        last.push_back(record?);
+    }
+    Ok((headers, last.into()))
+}
+
+/// Reservoir sampling: every record ends up in the sample with the same probability
+pub fn sample_records<I, R>(records: I, n: usize, rng: &mut R) -> Result<Vec<StringRecord>>
+where
+    I: Iterator<Item = csv::Result<StringRecord>>,
+    R: Rng,
+{
+    let mut reservoir = Vec::with_capacity(n.min(MAX_PREALLOCATED));
+    for (i, record) in records.enumerate() {
+        let record = record?;
+        if i < n {
+            reservoir.push((i, record));
+        } else {
+            let j = rng.gen_range(0..=i);
+            if j < n {
+                reservoir[j] = (i, record);
+            }
+        }
+    }
+    reservoir.sort_by_key(|(i, _)| *i);
+    Ok(reservoir.into_iter().map(|(_, record)| record).collect())
+}
+
+fn write_records(
+    output: &str,
+    format: OutputFormat,
+    writer_opts: &CsvWriterOpts,
+    headers: &[String],
+    records: Vec<StringRecord>,
+) -> Result<()> {
+    let mut writer = output_writer(output, format, writer_opts)?;
+    writer.write_headers(headers)?;
+    for record in records {
+        writer.write_record(&record_to_value(headers, string_values(&record)))?;
+    }
+    writer.finish()
+}
+
+/// Byte offset of the first record when the file can be parsed from any line
+/// without decoding, i.e. an uncompressed UTF-8 file
+fn seekable_data_start(input: &str, opts: &CsvReaderOpts) -> Result<Option<u64>> {
+    if input == "-" || Compression::detect(input)? != Compression::None {
+        return Ok(None);
+    }
+    let mut sample = Vec::new();
+    File::open(input)?
+        .take(TAIL_WINDOW)
+        .read_to_end(&mut sample)?;
+    let utf8 = matches!(opts.encoding, CsvEncoding::Auto)
+        || matches!(opts.encoding, CsvEncoding::Label(encoding) if encoding == UTF_8);
+    if !utf8 || detect_encoding(&sample) != UTF_8 {
+        return Ok(None);
+    }
+    let start = if opts.header {
+        let mut reader = csv_reader(File::open(input)?, opts);
+        reader.byte_headers()?;
+        reader.position().byte()
+    } else if sample.starts_with(b"\xef\xbb\xbf") {
+        3
+    } else {
+        0
+    };
+    Ok(Some(start))
+}
+
+/// Parse a window at the end of the file of `len` bytes, doubling it until it holds
+/// more than `n` records or reaches the first record. The window is cut at the first
+/// line break that starts a record: a line break inside a quoted field leaves an odd
+/// number of quotes up to the end of the file, so the first line start followed by
+/// an even number of quotes whose records have the header's width is taken. Returns
+/// none when the quotes cannot be counted, i.e. with an escape character
+fn tail_from_end<R: Read + Seek>(
+    file: &mut R,
+    len: u64,
+    opts: &CsvReaderOpts,
+    data_start: u64,
+    n: usize,
+    width: usize,
+) -> Result<Option<Vec<StringRecord>>> {
+    if n == 0 {
+        return Ok(Some(Vec::new()));
+    }
+    let opts = CsvReaderOpts {
+        header: false,
+        ..opts.clone()
+    };
+    let mut window = TAIL_WINDOW;
+    loop {
+        let start = len.saturating_sub(window).max(data_start);
+        let exact = start == data_start;
+        let mut buf = Vec::new();
+        file.seek(SeekFrom::Start(start))?;
+        file.by_ref().take(len - start).read_to_end(&mut buf)?;
+        if !exact && opts.escape.is_some() && buf.contains(&opts.quote) {
+            return Ok(None);
+        }
+        let records = if exact {
+            check_utf8(&buf, len)?;
+            Some(parse_window(&buf, &opts)?)
+        } else {
+            resync(&buf, &opts, len, width)?
+        };
+        match records {
+            Some(records) if exact || records.len() > n => {
+                let skip = records.len().saturating_sub(n);
+                return Ok(Some(records.into_iter().skip(skip).collect()));
+            }
+            _ => window *= 2,
+        }
+    }
+}
+
+/// Records of the window from its first line start outside quoted fields, none if
+/// no line start of the window is known to be one
+fn resync(
+    buf: &[u8],
+    opts: &CsvReaderOpts,
+    len: u64,
+    width: usize,
+) -> Result<Option<Vec<StringRecord>>> {
+    if let Some(first) = buf.iter().position(|b| *b == b'\n') {
+        check_utf8(&buf[first + 1..], len)?;
+    }
+    // quotes from every position to the end of the window
+    let mut quotes = buf.iter().filter(|b| **b == opts.quote).count();
+    for (i, b) in buf.iter().enumerate() {
+        if *b == opts.quote {
+            quotes -= 1;
+        }
+        if *b != b'\n' || quotes % 2 != 0 {
+            continue;
+        }
+        match parse_window(&buf[i + 1..], opts) {
+            Ok(records) if opts.flexible || records.iter().all(|r| r.len() == width) => {
+                return Ok(Some(records))
+            }
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+fn parse_window(bytes: &[u8], opts: &CsvReaderOpts) -> csv::Result<Vec<StringRecord>> {
+    csv_reader(bytes, opts).into_records().collect()
+}
+
+/// Nothing before the window is read, but what is read must be valid. `bytes` end
+/// the file of `len` bytes
+fn check_utf8(bytes: &[u8], len: u64) -> Result<()> {
+    if let Err(e) = std::str::from_utf8(bytes) {
+        let at = len - bytes.len() as u64 + e.valid_up_to() as u64;
+        anyhow::bail!(
+            "Input is not valid UTF-8 at byte {}, set its encoding with --encoding",
+            at
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_tail_and_sample() -> Result<()> {
+        let opts = CsvReaderOpts::default();
+        let (headers, records) = tail_records("assets/juventus.csv", &opts, 2)?;
+        assert_eq!(headers[0], "Name");
+        assert_eq!(records.len(), 2);
+        assert_eq!(&records[1][0], "Mario Mandzukic");
+
+        // quoted line breaks across the window boundary and a small file read exactly
+        let mut file = tempfile::NamedTempFile::new()?;
+        writeln!(file, "id,note")?;
+        for i in 0..20_000 {
+            writeln!(file, "{},\"line\nbreak, {}\"", i, i)?;
+        }
+        let path = file.path().to_str().unwrap();
+        let (_, records) = tail_records(path, &opts, 3)?;
+        let ids = records.iter().map(|r| &r[0]).collect::<Vec<_>>();
+        assert_eq!(ids, ["19997", "19998", "19999"]);
+        let (_, records) = tail_records(path, &opts, 30_000)?;
+        assert_eq!(records.len(), 20_000);
+
+        // a quoted field whose lines look like records of the right width
+        let mut file = tempfile::NamedTempFile::new()?;
+        write!(file, "id,note\n0,\"")?;
+        for i in 0..20_000 {
+            writeln!(file, "{},inside", i)?;
+        }
+        write!(file, "last,inside\"\n1,outside\n")?;
+        let path = file.path().to_str().unwrap();
+        let (_, records) = tail_records(path, &opts, 2)?;
+        let ids = records.iter().map(|r| &r[0]).collect::<Vec<_>>();
+        assert_eq!(ids, ["0", "1"]);
+
+        // a Windows-1252 byte past the bytes the encoding is detected from
+        let mut file = tempfile::NamedTempFile::new()?;
+        writeln!(file, "id,note")?;
+        for i in 0..20_000 {
+            writeln!(file, "{},plain", i)?;
+        }
+        file.write_all(b"20000,caf\xe9\n")?;
+        let path = file.path().to_str().unwrap();
+        let err = tail_records(path, &opts, 1).unwrap_err();
+        assert!(err.to_string().contains("not valid UTF-8"), "{}", err);
+
+        let sample = |seed| -> Result<Vec<StringRecord>> {
+            let reader = csv_input("assets/juventus.csv", &opts)?;
+            sample_records(
+                reader.into_records(),
+                5,
+                &mut ChaCha8Rng::seed_from_u64(seed),
+            )
+        };
+        assert_eq!(sample(42)?, sample(42)?);
+        assert_eq!(sample(42)?.len(), 5);
+        Ok(())
+    }
+
+    /// Counts the bytes read through it
+    struct CountingReader<R> {
+        inner: R,
+        read: u64,
+    }
+
+    impl<R: Read> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.read += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl<R: Seek> Seek for CountingReader<R> {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_tail_reads_only_the_end() -> Result<()> {
+        let mut content = b"Name,DOB,Note\n".to_vec();
+        let mut i = 0;
+        while content.len() < 4 * 1024 * 1024 {
+            writeln!(
+                content,
+                "Player {},\"Apr 18, 1990 ({})\",\"line\nbreak, \"\"{}\"\"\"",
+                i, i, i
+            )?;
+            i += 1;
+        }
+        let len = content.len() as u64;
+        let mut file = CountingReader {
+            inner: std::io::Cursor::new(content),
+            read: 0,
+        };
+        let opts = CsvReaderOpts::default();
+        let records = tail_from_end(&mut file, len, &opts, 14, 3, 3)?.unwrap();
+        let names = records.iter().map(|r| &r[0]).collect::<Vec<_>>();
+        let expected = (i - 3..i)
+            .map(|i| format!("Player {}", i))
+            .collect::<Vec<_>>();
+        assert_eq!(names, expected);
+        assert_eq!(&records[2][2], format!("line\nbreak, \"{}\"", i - 1));
+        assert_eq!(file.read, TAIL_WINDOW);
+        Ok(())
+    }
+
+    #[test]
+    fn test_huge_n() -> Result<()> {
+        let opts = CsvReaderOpts::default();
+        let reader = csv_input("assets/juventus.csv", &opts)?;
+        let sample = sample_records(
+            reader.into_records(),
+            usize::MAX,
+            &mut ChaCha8Rng::seed_from_u64(42),
+        )?;
+        assert_eq!(sample.len(), 27);
+
+        let (_, records) = tail_records("assets/juventus.csv", &opts, usize::MAX)?;
+        assert_eq!(records.len(), 27);
+        // compressed input is scanned from the start
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("juventus.csv.gz");
+        let path = path.to_str().unwrap();
+        let mut writer = crate::get_data_writer(path)?;
+        writer.write_all(&std::fs::read("assets/juventus.csv")?)?;
+        writer.finish()?;
+        drop(writer);
+        let (_, records) = tail_records(path, &opts, usize::MAX)?;
+        assert_eq!(records.len(), 27);
+        Ok(())
+    }
+}
@@ -0,0 +1,184 @@
+use anyhow::Result;
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use std::{cmp::Ordering, fs::File, io::Seek};
+
+use crate::{
+    column_index, csv_headers, csv_input, output_writer, record_to_value, string_values,
+    CsvReaderOpts, CsvWriterOpts, OutputFormat, RecordWriter, SortKey,
+};
+
+/// Sort a CSV file by one or more columns. Records are sorted in chunks of
+/// `chunk_rows`, chunks are spilled to temporary files and merged, so files larger
+/// than memory can be sorted
+pub fn process_csv_sort(
+    input: &str,
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    keys: &[SortKey],
+    chunk_rows: usize,
+    writer_opts: &CsvWriterOpts,
+) -> Result<()> {
+    let mut writer = output_writer(output, format, writer_opts)?;
+    sort_records(input, opts, keys, chunk_rows, writer.as_mut())?;
+    writer.finish()
+}
+
+/// Write the sorted records of a CSV file. The sort is stable
+pub fn sort_records(
+    input: &str,
+    opts: &CsvReaderOpts,
+    keys: &[SortKey],
+    chunk_rows: usize,
+    writer: &mut dyn RecordWriter,
+) -> Result<()> {
+    let mut reader = csv_input(input, opts)?;
+    let headers = csv_headers(&mut reader, opts)?;
+    let columns = keys
+        .iter()
+        .map(|k| Ok((column_index(&headers, &k.column)?, k)))
+        .collect::<Result<Vec<_>>>()?;
+    let compare = |a: &StringRecord, b: &StringRecord| compare_records(a, b, &columns);
+    writer.write_headers(&headers)?;
+
+    let chunk_rows = chunk_rows.max(1);
+    let mut chunk = Vec::new();
+    let mut spilled = Vec::new();
+    for record in reader.into_records() {
+        chunk.push(record?);
+        if chunk.len() >= chunk_rows {
+            chunk.sort_by(compare);
+            spilled.push(spill(&chunk)?);
+            chunk.clear();
+        }
+    }
+    chunk.sort_by(compare);
+    if spilled.is_empty() {
+        for record in &chunk {
+            writer.write_record(&record_to_value(&headers, string_values(record)))?;
+        }
+        return Ok(());
+    }
+    if !chunk.is_empty() {
+        spilled.push(spill(&chunk)?);
+    }
+    drop(chunk);
+
+    // k-way merge, ties go to the earlier chunk to keep the sort stable
+    let mut readers = spilled
+        .into_iter()
+        .map(|file| {
+            ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .from_reader(file)
+                .into_records()
+        })
+        .collect::<Vec<_>>();
+    let mut heads = readers
+        .iter_mut()
+        .map(|r| r.next().transpose())
+        .collect::<Result<Vec<_>, _>>()?;
+    loop {
+        let mut min: Option<usize> = None;
+        for (i, head) in heads.iter().enumerate() {
+            let Some(head) = head else { continue };
+            let smaller = match min.and_then(|m| heads[m].as_ref()) {
+                Some(current) => compare(head, current) == Ordering::Less,
+                None => true,
+            };
+            if smaller {
+                min = Some(i);
+            }
+        }
+        let Some(i) = min else { break };
+        let next = readers[i].next().transpose()?;
+        if let Some(record) = std::mem::replace(&mut heads[i], next) {
+            writer.write_record(&record_to_value(&headers, string_values(&record)))?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a sorted chunk to a temporary file, removed once it is closed
+fn spill(chunk: &[StringRecord]) -> Result<File> {
+    let mut file = tempfile::tempfile()?;
+    {
+        let mut writer = WriterBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_writer(&mut file);
+        for record in chunk {
+            writer.write_record(record)?;
+        }
+        writer.flush()?;
+    }
+    file.rewind()?;
+    Ok(file)
+}
+
+fn compare_records(a: &StringRecord, b: &StringRecord, keys: &[(usize, &SortKey)]) -> Ordering {
+    keys.iter()
+        .map(|(i, key)| {
+            let (a, b) = (a.get(*i).unwrap_or_default(), b.get(*i).unwrap_or_default());
+            let ordering = if key.numeric {
+                compare_numbers(a, b)
+            } else {
+                a.cmp(b)
+            };
+            if key.desc {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        })
+        .find(|o| o.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Numbers come first in numeric order, other values follow in string order
+fn compare_numbers(a: &str, b: &str) -> Ordering {
+    match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.total_cmp(&b),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record_writer;
+
+    fn sorted(keys: &[SortKey], chunk_rows: usize) -> Result<String> {
+        let mut buf = Vec::new();
+        let mut writer = record_writer(&mut buf, OutputFormat::Csv, &Default::default());
+        sort_records(
+            "assets/juventus.csv",
+            &Default::default(),
+            keys,
+            chunk_rows,
+            writer.as_mut(),
+        )?;
+        writer.finish()?;
+        drop(writer);
+        Ok(String::from_utf8(buf)?)
+    }
+
+    #[test]
+    fn test_external_sort_matches_in_memory() -> Result<()> {
+        let keys = vec![
+            "Position".parse::<SortKey>().unwrap(),
+            "Kit Number:num:desc".parse().unwrap(),
+        ];
+        let in_memory = sorted(&keys, usize::MAX)?;
+        assert_eq!(sorted(&keys, 4)?, in_memory);
+
+        let lines = in_memory.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 28);
+        assert_eq!(lines[0], "Name,Position,DOB,Nationality,Kit Number");
+        assert!(lines[1].starts_with("Rodrigo Bentancur,Central Midfield"));
+        Ok(())
+    }
+}
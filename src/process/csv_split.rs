@@ -0,0 +1,325 @@
+use anyhow::Result;
+use csv::{StringRecord, Writer, WriterBuilder};
+use serde_json::{Map, Value};
+use std::collections::{hash_map::Entry, HashMap, HashSet};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    append_data_writer, column_index, csv_headers, csv_input, finish_csv, get_data_writer,
+    output_writer, split_compression, CsvReaderOpts, CsvWriterOpts, DataWriter, OutputFormat,
+};
+
+/// How the records of a CSV file are spread over the chunk files
+#[derive(Debug, Clone, Copy)]
+pub enum SplitBy<'a> {
+    /// At most this many records per file, files are numbered
+    Rows(usize),
+    /// One file per distinct value of the column, files are named after the value
+    Column(&'a str),
+}
+
+type ChunkWriter = Writer<BufWriter<DataWriter>>;
+
+/// Chunk files a split on a column keeps open. When a new value needs one more, the
+/// least recently written file is closed and reopened for appending when its value
+/// comes back
+const MAX_OPEN_CHUNKS: usize = 64;
+
+/// Split a CSV file into chunk files in `dir`, each starting with the header.
+/// Files are named after the input, e.g. `juventus_001.csv` or `juventus_Goalkeeper.csv`,
+/// and are compressed like the input. Returns the files written with their record count
+pub fn process_csv_split(
+    input: &str,
+    dir: &Path,
+    prefix: Option<&str>,
+    opts: &CsvReaderOpts,
+    by: SplitBy,
+) -> Result<Vec<(PathBuf, usize)>> {
+    let (prefix, ext) = chunk_name(input, prefix);
+    let mut reader = csv_input(input, opts)?;
+    let headers = csv_headers(&mut reader, opts)?;
+    let header =
+        (opts.header || opts.columns.is_some()).then(|| StringRecord::from(headers.clone()));
+    let chunk_path = |name: &str| dir.join(format!("{}_{}.{}", prefix, name, ext));
+    // a new file starts with the header, a reopened one already has it
+    let open_chunk = |path: &Path, append: bool| -> Result<ChunkWriter> {
+        let path = path.to_string_lossy();
+        let writer = match append {
+            true => append_data_writer(&path)?,
+            false => get_data_writer(&path)?,
+        };
+        let mut writer = WriterBuilder::new()
+            .delimiter(opts.delimiter)
+            .quote(opts.quote)
+            .flexible(opts.flexible)
+            .from_writer(BufWriter::new(writer));
+        if let Some(header) = header.as_ref().filter(|_| !append) {
+            writer.write_record(header)?;
+        }
+        Ok(writer)
+    };
+
+    let mut chunks = Vec::new();
+    match by {
+        SplitBy::Rows(rows) => {
+            anyhow::ensure!(rows > 0, "The number of rows per file must be positive");
+            let mut current: Option<(PathBuf, ChunkWriter, usize)> = None;
+            for record in reader.records() {
+                let record = record?;
+                let (_, writer, count) = match current.take() {
+                    Some((path, writer, count)) if count < rows => {
+                        current.insert((path, writer, count))
+                    }
+                    full => {
+                        if let Some((path, writer, count)) = full {
+                            finish_csv(writer)?;
+                            chunks.push((path, count));
+                        }
+                        let path = chunk_path(&format!("{:03}", chunks.len() + 1));
+                        let writer = open_chunk(&path, false)?;
+                        current.insert((path, writer, 0))
+                    }
+                };
+                writer.write_record(&record)?;
+                *count += 1;
+            }
+            if let Some((path, writer, count)) = current {
+                finish_csv(writer)?;
+                chunks.push((path, count));
+            }
+        }
+        SplitBy::Column(column) => {
+            let index = column_index(&headers, column)?;
+            // chunk index by value, values that clean up to the same file name get a number
+            let mut files = HashMap::new();
+            let mut names = HashSet::new();
+            // open writers by chunk index, with the record they last wrote
+            let mut open = HashMap::<usize, (ChunkWriter, usize)>::new();
+            for (n, record) in reader.records().enumerate() {
+                let record = record?;
+                let value = record.get(index).unwrap_or_default();
+                let i = match files.get(value) {
+                    Some(i) => *i,
+                    None => {
+                        let name = unique_name(&mut names, file_name(value));
+                        chunks.push((chunk_path(&name), 0));
+                        files.insert(value.to_string(), chunks.len() - 1);
+                        chunks.len() - 1
+                    }
+                };
+                if !open.contains_key(&i) && open.len() >= MAX_OPEN_CHUNKS {
+                    let oldest = open.iter().min_by_key(|(_, (_, last))| *last);
+                    if let Some((writer, _)) = oldest.map(|(j, _)| *j).and_then(|j| open.remove(&j))
+                    {
+                        finish_csv(writer)?;
+                    }
+                }
+                let (writer, last) = match open.entry(i) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        let (path, count) = &chunks[i];
+                        entry.insert((open_chunk(path, *count > 0)?, n))
+                    }
+                };
+                writer.write_record(&record)?;
+                *last = n;
+                chunks[i].1 += 1;
+            }
+            for (writer, _) in open.into_values() {
+                finish_csv(writer)?;
+            }
+        }
+    }
+    Ok(chunks)
+}
+
+/// Concatenate CSV files, columns are matched by name. The header is the union of all
+/// headers in the order they are first seen, missing columns are left empty. Every
+/// input is opened once to read its header and then read to the end, so stdin can
+/// be one of them
+pub fn process_csv_merge(
+    inputs: &[String],
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    writer_opts: &CsvWriterOpts,
+) -> Result<()> {
+    anyhow::ensure!(
+        inputs.iter().filter(|input| *input == "-").count() <= 1,
+        "Stdin can only be merged once"
+    );
+    let mut columns = Vec::<String>::new();
+    let mut readers = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let mut reader = csv_input(input, opts)?;
+        let headers = csv_headers(&mut reader, opts)?;
+        for (i, header) in headers.iter().enumerate() {
+            if headers[..i].contains(header) {
+                anyhow::bail!("{} has the column {:?} more than once", input, header);
+            }
+            if !columns.contains(header) {
+                columns.push(header.clone());
+            }
+        }
+        readers.push((reader, headers));
+    }
+
+    let mut writer = output_writer(output, format, writer_opts)?;
+    writer.write_headers(&columns)?;
+    for (reader, headers) in readers {
+        for record in reader.into_records() {
+            let record = record?;
+            let mut map = columns
+                .iter()
+                .map(|c| (c.clone(), Value::Null))
+                .collect::<Map<_, _>>();
+            for (header, field) in headers.iter().zip(record.iter()) {
+                map.insert(header.clone(), Value::String(field.to_string()));
+            }
+            writer.write_record(&Value::Object(map))?;
+        }
+    }
+    writer.finish()
+}
+
+/// Prefix and extension of the chunk files: `data/players.csv.gz` gives (`players`, `csv.gz`)
+fn chunk_name(input: &str, prefix: Option<&str>) -> (String, String) {
+    let (path, compression) = split_compression(input);
+    let ext = match compression {
+        Some(compression) => format!("csv.{}", compression),
+        None => "csv".to_string(),
+    };
+    let prefix = prefix.map(|p| p.to_string()).unwrap_or_else(|| {
+        Path::new(path)
+            .file_stem()
+            .filter(|_| input != "-")
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "chunk".to_string())
+    });
+    (prefix, ext)
+}
+
+/// Keep letters, digits, `-` and `_` of a value so it can be used in a file name
+fn file_name(value: &str) -> String {
+    let name = value
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    if name.is_empty() {
+        "empty".to_string()
+    } else {
+        name
+    }
+}
+
+fn unique_name(names: &mut HashSet<String>, name: String) -> String {
+    let mut unique = name.clone();
+    let mut n = 1;
+    while !names.insert(unique.clone()) {
+        n += 1;
+        unique = format!("{}_{}", name, n);
+    }
+    unique
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_data_reader;
+    use std::fs;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_split_and_merge() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let opts = CsvReaderOpts::default();
+        let chunks = process_csv_split(
+            "assets/juventus.csv",
+            dir.path(),
+            None,
+            &opts,
+            SplitBy::Rows(10),
+        )?;
+        let counts = chunks.iter().map(|(_, n)| *n).collect::<Vec<_>>();
+        assert_eq!(counts, [10, 10, 7]);
+        assert!(chunks[2].0.ends_with("juventus_003.csv"));
+        let content = fs::read_to_string(&chunks[2].0)?;
+        assert!(content.starts_with("Name,Position,DOB,Nationality,Kit Number\n"));
+
+        let chunks = process_csv_split(
+            "assets/juventus.csv",
+            dir.path(),
+            Some("pos"),
+            &opts,
+            SplitBy::Column("Position"),
+        )?;
+        assert!(chunks[0].0.ends_with("pos_Goalkeeper.csv"));
+        assert_eq!(chunks.iter().map(|(_, n)| n).sum::<usize>(), 27);
+
+        let a = dir.path().join("a.csv");
+        let b = dir.path().join("b.csv");
+        fs::write(&a, "id,name\n1,Buffon\n")?;
+        fs::write(&b, "name,kit\nPerin,37\n")?;
+        let out = dir.path().join("merged.csv");
+        let inputs = [a, b].map(|p| p.to_string_lossy().into_owned());
+        let out = out.to_str().unwrap();
+        process_csv_merge(&inputs, out, OutputFormat::Csv, &opts, &Default::default())?;
+        assert_eq!(
+            fs::read_to_string(out)?,
+            "id,name,kit\n1,Buffon,\n,Perin,37\n"
+        );
+
+        fs::write(&inputs[1], "name,name\nPerin,Mattia\n")?;
+        let err = process_csv_merge(&inputs, out, OutputFormat::Csv, &opts, &Default::default())
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("\"name\" more than once"),
+            "{}",
+            err
+        );
+        let stdin = ["-".to_string(), "-".to_string()];
+        assert!(
+            process_csv_merge(&stdin, out, OutputFormat::Csv, &opts, &Default::default()).is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_reopens_closed_chunks() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let input = dir.path().join("ids.csv.gz");
+        let input = input.to_str().unwrap();
+        let mut writer = get_data_writer(input)?;
+        writeln!(writer, "id,n")?;
+        for n in 0..MAX_OPEN_CHUNKS * 3 {
+            writeln!(writer, "{},{}", n % (MAX_OPEN_CHUNKS + 10), n)?;
+        }
+        writer.finish()?;
+        drop(writer);
+
+        let chunks = process_csv_split(
+            input,
+            dir.path(),
+            None,
+            &CsvReaderOpts::default(),
+            SplitBy::Column("id"),
+        )?;
+        assert_eq!(chunks.len(), MAX_OPEN_CHUNKS + 10);
+        assert!(chunks[5].0.ends_with("ids_5.csv.gz"));
+        let mut content = String::new();
+        get_data_reader(&chunks[5].0.to_string_lossy())?.read_to_string(&mut content)?;
+        let step = MAX_OPEN_CHUNKS + 10;
+        let expected = format!("id,n\n5,5\n5,{}\n5,{}\n", 5 + step, 5 + 2 * step);
+        assert_eq!(content, expected);
+        assert_eq!(chunks[5].1, 3);
+        Ok(())
+    }
+}
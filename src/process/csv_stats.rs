@@ -0,0 +1,317 @@
+use anyhow::Result;
+use hyperloglogplus::{HyperLogLog, HyperLogLogPlus};
+use serde_json::{json, Value};
+use std::collections::{hash_map::RandomState, HashMap};
+
+use crate::{
+    csv_headers, csv_input, detect_type, output_writer, ColumnType, CsvReaderOpts, CsvTypeOpts,
+    CsvWriterOpts, OutputFormat,
+};
+
+/// Precision of the HyperLogLog sketch, about 0.8% standard error
+const HLL_PRECISION: u8 = 14;
+/// Number of counters kept to find frequent values in approximate mode
+const APPROX_TOP_CAPACITY: usize = 1024;
+
+/// Distinct values of a column, counted exactly or estimated
+enum Distinct {
+    Exact(HashMap<String, usize>),
+    Approx {
+        hll: Box<HyperLogLogPlus<str, RandomState>>,
+        // Misra-Gries counters, a lower bound of the frequency of heavy hitters
+        top: HashMap<String, usize>,
+        // total subtracted from the counters, i.e. how far below the real count they can be
+        error: usize,
+    },
+}
+
+/// Statistics of a single column, updated one value at a time
+pub struct ColumnStats {
+    name: String,
+    column_type: ColumnType,
+    count: usize,
+    nulls: usize,
+    max_length: usize,
+    min_str: Option<String>,
+    max_str: Option<String>,
+    min_num: f64,
+    max_num: f64,
+    // int bounds are kept apart so they do not lose precision above 2^53
+    min_int: Option<i64>,
+    max_int: Option<i64>,
+    // Welford's online mean and variance
+    numbers: usize,
+    mean: f64,
+    m2: f64,
+    distinct: Distinct,
+}
+
+impl ColumnStats {
+    pub fn new(name: &str, approx: bool) -> Result<Self> {
+        let distinct = if approx {
+            let hll = HyperLogLogPlus::new(HLL_PRECISION, RandomState::new())
+                .map_err(|e| anyhow::anyhow!("Cannot create HyperLogLog: {:?}", e))?;
+            Distinct::Approx {
+                hll: Box::new(hll),
+                top: HashMap::new(),
+                error: 0,
+            }
+        } else {
+            Distinct::Exact(HashMap::new())
+        };
+        Ok(Self {
+            name: name.to_string(),
+            column_type: ColumnType::Null,
+            count: 0,
+            nulls: 0,
+            max_length: 0,
+            min_str: None,
+            max_str: None,
+            min_num: f64::INFINITY,
+            max_num: f64::NEG_INFINITY,
+            min_int: None,
+            max_int: None,
+            numbers: 0,
+            mean: 0.0,
+            m2: 0.0,
+            distinct,
+        })
+    }
+
+    pub fn update(&mut self, value: &str, type_opts: &CsvTypeOpts) {
+        let value_type = detect_type(value, type_opts);
+        if value_type == ColumnType::Null {
+            self.nulls += 1;
+            return;
+        }
+        self.count += 1;
+        self.column_type = self.column_type.merge(value_type);
+        self.max_length = self.max_length.max(value.chars().count());
+        if self.min_str.as_deref().is_none_or(|m| value < m) {
+            self.min_str = Some(value.to_string());
+        }
+        if self.max_str.as_deref().is_none_or(|m| value > m) {
+            self.max_str = Some(value.to_string());
+        }
+        if let (ColumnType::Int | ColumnType::Float, Ok(n)) = (value_type, value.trim().parse()) {
+            self.add_number(n);
+        }
+        if let (ColumnType::Int, Ok(n)) = (value_type, value.trim().parse::<i64>()) {
+            self.min_int = Some(self.min_int.map_or(n, |m| m.min(n)));
+            self.max_int = Some(self.max_int.map_or(n, |m| m.max(n)));
+        }
+        match &mut self.distinct {
+            Distinct::Exact(counts) => *counts.entry(value.to_string()).or_default() += 1,
+            Distinct::Approx { hll, top, error } => {
+                hll.insert(value);
+                if let Some(count) = top.get_mut(value) {
+                    *count += 1;
+                } else {
+                    if top.len() >= APPROX_TOP_CAPACITY {
+                        *error += decrement_counters(top);
+                    }
+                    top.insert(value.to_string(), 1);
+                }
+            }
+        }
+    }
+
+    fn add_number(&mut self, n: f64) {
+        self.numbers += 1;
+        self.min_num = self.min_num.min(n);
+        self.max_num = self.max_num.max(n);
+        let delta = n - self.mean;
+        self.mean += delta / self.numbers as f64;
+        self.m2 += delta * (n - self.mean);
+    }
+
+    /// Summary of the column, `top` most frequent values are listed
+    pub fn to_value(&mut self, top: usize) -> Value {
+        let numeric = matches!(self.column_type, ColumnType::Int | ColumnType::Float);
+        let (min, max, mean, stddev) = if numeric && self.numbers > 0 {
+            let stddev = if self.numbers > 1 {
+                json!((self.m2 / (self.numbers - 1) as f64).sqrt())
+            } else {
+                Value::Null
+            };
+            let (min, max) = match self.column_type {
+                ColumnType::Int => (json!(self.min_int), json!(self.max_int)),
+                _ => (json!(self.min_num), json!(self.max_num)),
+            };
+            (min, max, json!(self.mean), stddev)
+        } else {
+            (
+                json!(self.min_str),
+                json!(self.max_str),
+                Value::Null,
+                Value::Null,
+            )
+        };
+        let (distinct, counts, error) = match &mut self.distinct {
+            Distinct::Exact(counts) => (counts.len() as u64, &*counts, None),
+            Distinct::Approx { hll, top, error } => {
+                (hll.count().round() as u64, &*top, Some(*error))
+            }
+        };
+        let mut frequent = counts.iter().collect::<Vec<_>>();
+        frequent.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        let frequent = frequent
+            .into_iter()
+            .take(top)
+            .map(|(value, count)| match error {
+                // approximate counts are only known to be within a range
+                Some(error) => json!({
+                    "value": value,
+                    "min_count": count,
+                    "max_count": count + error,
+                }),
+                None => json!({"value": value, "count": count}),
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "column": self.name,
+            "type": self.column_type.to_string(),
+            "count": self.count,
+            "nulls": self.nulls,
+            "distinct": distinct,
+            "min": min,
+            "max": max,
+            "mean": mean,
+            "stddev": stddev,
+            "max_length": self.max_length,
+            "top": frequent,
+        })
+    }
+}
+
+/// Batched Misra-Gries decrement: subtract the median count from every counter and
+/// drop the ones that reach zero. At least half the counters are freed, so the cost is
+/// amortized over the next insertions. Returns the amount subtracted
+fn decrement_counters(top: &mut HashMap<String, usize>) -> usize {
+    let mut counts = top.values().copied().collect::<Vec<_>>();
+    let mid = counts.len() / 2;
+    let median = *counts.select_nth_unstable(mid).1;
+    top.retain(|_, count| {
+        *count = count.saturating_sub(median);
+        *count > 0
+    });
+    median
+}
+
+/// Profile every column of a CSV file
+pub fn process_csv_stats(
+    input: &str,
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    top: usize,
+    approx: bool,
+) -> Result<()> {
+    let stats = csv_stats(input, opts, top, approx)?;
+    let writer_opts = CsvWriterOpts::default();
+    let mut writer = output_writer(output, format, &writer_opts)?;
+    for mut column in stats {
+        // tables cannot show nested values, list the frequent values inline instead
+        if format.is_table() {
+            let top = column["top"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|t| {
+                    let value = t["value"].as_str().unwrap_or_default();
+                    match (&t["min_count"], &t["max_count"]) {
+                        (Value::Null, _) => format!("{} ({})", value, t["count"]),
+                        (min, max) if min == max => format!("{} ({})", value, min),
+                        (min, max) => format!("{} ({}-{})", value, min, max),
+                    }
+                })
+                .collect::<Vec<_>>();
+            column["top"] = Value::String(top.join(", "));
+        }
+        writer.write_record(&column)?;
+    }
+    writer.finish()
+}
+
+/// Compute the statistics of every column
+pub fn csv_stats(
+    input: &str,
+    opts: &CsvReaderOpts,
+    top: usize,
+    approx: bool,
+) -> Result<Vec<Value>> {
+    let mut reader = csv_input(input, opts)?;
+    let headers = csv_headers(&mut reader, opts)?;
+    let type_opts = CsvTypeOpts {
+        infer_types: true,
+        infer_dates: true,
+        ..Default::default()
+    };
+    let mut columns = headers
+        .iter()
+        .map(|h| ColumnStats::new(h, approx))
+        .collect::<Result<Vec<_>>>()?;
+    for record in reader.records() {
+        let record = record?;
+        for (i, value) in record.iter().enumerate() {
+            if let Some(column) = columns.get_mut(i) {
+                column.update(value, &type_opts);
+            }
+        }
+    }
+    Ok(columns.iter_mut().map(|c| c.to_value(top)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_stats_juventus() -> Result<()> {
+        let stats = csv_stats("assets/juventus.csv", &Default::default(), 2, false)?;
+        assert_eq!(stats.len(), 5);
+
+        let position = &stats[1];
+        assert_eq!(position["type"], "string");
+        assert_eq!(position["count"], 27);
+        assert_eq!(position["distinct"], 10);
+
+        let kit = &stats[4];
+        assert_eq!(kit["column"], "Kit Number");
+        assert_eq!(kit["type"], "int");
+        assert_eq!(kit["nulls"], 0);
+        assert_eq!(kit["min"], 1);
+        assert_eq!(kit["max"], 77);
+        assert_eq!(kit["top"].as_array().map(|t| t.len()), Some(2));
+
+        let approx = csv_stats("assets/juventus.csv", &Default::default(), 2, true)?;
+        assert_eq!(approx[1]["distinct"], 10);
+        assert_eq!(
+            approx[1]["top"][0]["min_count"],
+            approx[1]["top"][0]["max_count"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_stats_bounds() {
+        let type_opts = CsvTypeOpts::default();
+        let mut stats = ColumnStats::new("id", true).unwrap();
+        for i in 0..(APPROX_TOP_CAPACITY * 3) {
+            stats.update(&(9_007_199_254_740_993 + i as i64).to_string(), &type_opts);
+            stats.update("frequent", &type_opts);
+        }
+        let value = stats.to_value(1);
+        assert_eq!(value["top"][0]["value"], "frequent");
+
+        let mut stats = ColumnStats::new("id", true).unwrap();
+        for i in 0..(APPROX_TOP_CAPACITY * 3) {
+            stats.update(&(9_007_199_254_740_993 + i as i64).to_string(), &type_opts);
+        }
+        let value = stats.to_value(1);
+        assert_eq!(value["min"], 9_007_199_254_740_993_i64);
+        assert_eq!(value["max"], 9_007_199_254_740_993_i64 + 3071);
+        let top = &value["top"][0];
+        assert!(top["min_count"].as_u64() <= top["max_count"].as_u64());
+    }
+}
@@ -0,0 +1,298 @@
+use anyhow::Result;
+use serde_json::Value;
+use std::io::Write;
+
+use crate::{cell_to_string, Align, CsvWriterOpts, RecordWriter, WriteFinish};
+
+/// Kind of table rendered by [`TableWriter`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableStyle {
+    Markdown,
+    Html,
+    Text,
+}
+
+/// Render records as a table. Column widths depend on every row, so records are
+/// kept until `finish`
+pub struct TableWriter<W> {
+    writer: W,
+    style: TableStyle,
+    align: Align,
+    max_width: Option<usize>,
+    headers: Vec<String>,
+    records: Vec<Value>,
+}
+
+impl<W: WriteFinish> TableWriter<W> {
+    pub fn new(writer: W, style: TableStyle, opts: &CsvWriterOpts) -> Self {
+        Self {
+            writer,
+            style,
+            align: opts.align,
+            max_width: opts.max_width,
+            headers: Vec::new(),
+            records: Vec::new(),
+        }
+    }
+
+    fn render(&mut self) -> Result<()> {
+        let mut headers = self.headers.clone();
+        for record in self.records.iter().filter_map(|r| r.as_object()) {
+            for key in record.keys() {
+                if !headers.contains(key) {
+                    headers.push(key.clone());
+                }
+            }
+        }
+        let rows = self
+            .records
+            .iter()
+            .map(|r| {
+                headers
+                    .iter()
+                    .map(|h| r.get(h).map(cell_to_string).unwrap_or_default())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let aligns = (0..headers.len())
+            .map(|i| match self.align {
+                Align::Auto if is_numeric_column(&rows, i) => Align::Right,
+                Align::Auto => Align::Left,
+                align => align,
+            })
+            .collect::<Vec<_>>();
+
+        let style = self.style;
+        let cell = |s: &str| escape(&truncate(s, self.max_width), style);
+        let headers = headers.iter().map(|h| cell(h)).collect::<Vec<_>>();
+        let rows = rows
+            .iter()
+            .map(|row| row.iter().map(|c| cell(c)).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        match self.style {
+            TableStyle::Markdown => render_markdown(&mut self.writer, &headers, &rows, &aligns),
+            TableStyle::Html => render_html(&mut self.writer, &headers, &rows, &aligns),
+            TableStyle::Text => render_text(&mut self.writer, &headers, &rows, &aligns),
+        }
+    }
+}
+
+impl<W: WriteFinish> RecordWriter for TableWriter<W> {
+    fn write_headers(&mut self, headers: &[String]) -> Result<()> {
+        self.headers = headers.to_vec();
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        self.records.push(record.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.render()?;
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+fn render_markdown<W: Write>(
+    writer: &mut W,
+    headers: &[String],
+    rows: &[Vec<String>],
+    aligns: &[Align],
+) -> Result<()> {
+    let widths = column_widths(headers, rows);
+    let line = |cells: &[String]| {
+        let cells = cells
+            .iter()
+            .zip(&widths)
+            .zip(aligns)
+            .map(|((c, w), a)| pad(c, *w, *a))
+            .collect::<Vec<_>>();
+        format!("| {} |", cells.join(" | "))
+    };
+    writeln!(writer, "{}", line(headers))?;
+    let separators = widths
+        .iter()
+        .zip(aligns)
+        .map(|(w, a)| {
+            let w = (*w).max(3);
+            match a {
+                Align::Right => format!("{}:", "-".repeat(w - 1)),
+                Align::Center => format!(":{}:", "-".repeat(w - 2)),
+                _ => "-".repeat(w),
+            }
+        })
+        .collect::<Vec<_>>();
+    writeln!(writer, "| {} |", separators.join(" | "))?;
+    for row in rows {
+        writeln!(writer, "{}", line(row))?;
+    }
+    Ok(())
+}
+
+fn render_html<W: Write>(
+    writer: &mut W,
+    headers: &[String],
+    rows: &[Vec<String>],
+    aligns: &[Align],
+) -> Result<()> {
+    let cells = |tag: &str, cells: &[String]| {
+        cells
+            .iter()
+            .zip(aligns)
+            .map(|(c, a)| match a {
+                Align::Right | Align::Center => {
+                    format!("<{tag} style=\"text-align: {}\">{c}</{tag}>", a)
+                }
+                _ => format!("<{tag}>{c}</{tag}>"),
+            })
+            .collect::<String>()
+    };
+    writeln!(writer, "<table>")?;
+    writeln!(writer, "  <thead>")?;
+    writeln!(writer, "    <tr>{}</tr>", cells("th", headers))?;
+    writeln!(writer, "  </thead>")?;
+    writeln!(writer, "  <tbody>")?;
+    for row in rows {
+        writeln!(writer, "    <tr>{}</tr>", cells("td", row))?;
+    }
+    writeln!(writer, "  </tbody>")?;
+    writeln!(writer, "</table>")?;
+    Ok(())
+}
+
+fn render_text<W: Write>(
+    writer: &mut W,
+    headers: &[String],
+    rows: &[Vec<String>],
+    aligns: &[Align],
+) -> Result<()> {
+    let widths = column_widths(headers, rows);
+    let line = |cells: &[String]| {
+        let cells = cells
+            .iter()
+            .zip(&widths)
+            .zip(aligns)
+            .map(|((c, w), a)| pad(c, *w, *a))
+            .collect::<Vec<_>>();
+        cells.join("  ").trim_end().to_string()
+    };
+    writeln!(writer, "{}", line(headers))?;
+    let separators = widths.iter().map(|w| "-".repeat(*w)).collect::<Vec<_>>();
+    writeln!(writer, "{}", separators.join("  "))?;
+    for row in rows {
+        writeln!(writer, "{}", line(row))?;
+    }
+    Ok(())
+}
+
+fn column_widths(headers: &[String], rows: &[Vec<String>]) -> Vec<usize> {
+    headers
+        .iter()
+        .enumerate()
+        .map(|(i, h)| {
+            rows.iter()
+                .map(|r| r[i].chars().count())
+                .chain([h.chars().count()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+fn is_numeric_column(rows: &[Vec<String>], i: usize) -> bool {
+    let mut cells = rows.iter().map(|r| r[i].as_str()).filter(|c| !c.is_empty());
+    let mut any = false;
+    let numeric = cells.all(|c| {
+        any = true;
+        c.parse::<f64>().is_ok()
+    });
+    any && numeric
+}
+
+fn pad(s: &str, width: usize, align: Align) -> String {
+    let fill = width.saturating_sub(s.chars().count());
+    match align {
+        Align::Right => format!("{}{}", " ".repeat(fill), s),
+        Align::Center => format!(
+            "{}{}{}",
+            " ".repeat(fill / 2),
+            s,
+            " ".repeat(fill - fill / 2)
+        ),
+        _ => format!("{}{}", s, " ".repeat(fill)),
+    }
+}
+
+/// Cut a cell to `max_width` characters, ending with an ellipsis
+fn truncate(s: &str, max_width: Option<usize>) -> String {
+    match max_width {
+        Some(max) if s.chars().count() > max => {
+            let kept = s.chars().take(max.saturating_sub(1)).collect::<String>();
+            format!("{}…", kept)
+        }
+        _ => s.to_string(),
+    }
+}
+
+fn escape(s: &str, style: TableStyle) -> String {
+    match style {
+        TableStyle::Markdown => s
+            .replace('\\', "\\\\")
+            .replace('|', "\\|")
+            .replace("\r\n", "<br>")
+            .replace('\n', "<br>"),
+        TableStyle::Html => s
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+            .replace('\'', "&#39;"),
+        TableStyle::Text => s.replace(['\r', '\n'], " "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{serialize_records, OutputFormat};
+    use serde_json::json;
+
+    #[test]
+    fn test_markdown_table() -> Result<()> {
+        let records = vec![
+            json!({"Name": "Buffon | GK", "Kit Number": 77}),
+            json!({"Name": "Perin", "Kit Number": 37}),
+        ];
+        let content = serialize_records(&records, OutputFormat::Markdown, &Default::default())?;
+        assert_eq!(
+            content,
+            "| Name         | Kit Number |\n\
+             | ------------ | ---------: |\n\
+             | Buffon \\| GK |         77 |\n\
+             | Perin        |         37 |\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_html_and_text_table() -> Result<()> {
+        let records = vec![json!({"Name": "<b>Buffon</b> & co", "Kit Number": 77})];
+        let opts = CsvWriterOpts {
+            max_width: Some(8),
+            align: Align::Left,
+            ..Default::default()
+        };
+        let content = serialize_records(&records, OutputFormat::Html, &opts)?;
+        assert!(content.contains("<td>&lt;b&gt;Buff…</td><td>77</td>"));
+
+        let content = serialize_records(&records, OutputFormat::Text, &opts)?;
+        assert_eq!(
+            content,
+            "Name      Kit Num…\n--------  --------\n<b>Buff…  77\n"
+        );
+        Ok(())
+    }
+}
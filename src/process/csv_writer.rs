@@ -0,0 +1,393 @@
+use anyhow::Result;
+use csv::WriterBuilder;
+use serde_json::{Map, Value};
+use std::io::BufWriter;
+
+use crate::{
+    cell_to_string, column_index, csv_headers, csv_input, get_data_writer, record_to_value,
+    unflatten_record, CsvReaderOpts, CsvWriterOpts, OutputFormat, TableStyle, TableWriter,
+    WriteFinish,
+};
+
+/// Write records one by one so the output never has to be held in memory
+pub trait RecordWriter {
+    /// Set the columns before the first record, so an empty result still gets a header.
+    /// Formats without a header ignore it
+    fn write_headers(&mut self, _headers: &[String]) -> Result<()> {
+        Ok(())
+    }
+    /// Write a single record
+    fn write_record(&mut self, record: &Value) -> Result<()>;
+    /// Write what is left after the last record and flush
+    fn finish(&mut self) -> Result<()>;
+}
+
+/// Create the writer for the output format
+pub fn record_writer<'a, W: WriteFinish + 'a>(
+    writer: W,
+    format: OutputFormat,
+    opts: &CsvWriterOpts,
+) -> Box<dyn RecordWriter + 'a> {
+    let writer: Box<dyn RecordWriter + 'a> = match format {
+        OutputFormat::Csv => Box::new(CsvRecordWriter::new(writer, opts.out_delimiter)),
+        OutputFormat::Json if opts.stream => Box::new(JsonLinesArrayWriter::new(writer)),
+        OutputFormat::Json => Box::new(JsonWriter::new(writer)),
+        OutputFormat::Ndjson => Box::new(NdjsonWriter { writer }),
+        OutputFormat::Yaml => Box::new(YamlWriter::new(writer)),
+        OutputFormat::Toml => Box::new(TomlWriter::new(writer, &opts.toml_key)),
+        OutputFormat::Markdown => Box::new(TableWriter::new(writer, TableStyle::Markdown, opts)),
+        OutputFormat::Html => Box::new(TableWriter::new(writer, TableStyle::Html, opts)),
+        OutputFormat::Text => Box::new(TableWriter::new(writer, TableStyle::Text, opts)),
+    };
+    if opts.unflatten {
+        Box::new(UnflattenWriter { inner: writer })
+    } else {
+        writer
+    }
+}
+
+/// Create the writer for the output file, "-" for stdout
+pub fn output_writer(
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvWriterOpts,
+) -> Result<Box<dyn RecordWriter>> {
+    let writer = BufWriter::new(get_data_writer(output)?);
+    Ok(record_writer(writer, format, opts))
+}
+
+/// Serialize all records to a string with the writer of the output format
+pub fn serialize_records(
+    records: &[Value],
+    format: OutputFormat,
+    opts: &CsvWriterOpts,
+) -> Result<String> {
+    let mut buf = Vec::new();
+    let mut writer = record_writer(&mut buf, format, opts);
+    for record in records {
+        writer.write_record(record)?;
+    }
+    writer.finish()?;
+    drop(writer);
+    Ok(String::from_utf8(buf)?)
+}
+
+/// Replace the values of some columns with `f`, called with the column name and the
+/// value. The other columns are written as they are
+pub fn map_columns(
+    input: &str,
+    output: &str,
+    format: OutputFormat,
+    opts: &CsvReaderOpts,
+    columns: &[String],
+    writer_opts: &CsvWriterOpts,
+    f: impl Fn(&str, &str) -> Result<String>,
+) -> Result<()> {
+    let mut reader = csv_input(input, opts)?;
+    let headers = csv_headers(&mut reader, opts)?;
+    let mapped = columns
+        .iter()
+        .map(|c| column_index(&headers, c))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut writer = output_writer(output, format, writer_opts)?;
+    writer.write_headers(&headers)?;
+    for (line, record) in reader.records().enumerate() {
+        let values = record?
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                let field = if mapped.contains(&i) {
+                    f(&headers[i], field).map_err(|e| {
+                        anyhow::anyhow!("Record {}, column {:?}: {}", line + 1, headers[i], e)
+                    })?
+                } else {
+                    field.to_string()
+                };
+                Ok(field.into())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        writer.write_record(&record_to_value(&headers, values))?;
+    }
+    writer.finish()
+}
+
+/// Rebuild nested documents from dotted and indexed column names before writing
+struct UnflattenWriter<'a> {
+    inner: Box<dyn RecordWriter + 'a>,
+}
+
+impl RecordWriter for UnflattenWriter<'_> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        self.inner.write_record(&unflatten_record(record)?)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.inner.finish()
+    }
+}
+
+/// CSV with the headers given up front, or else the keys of the first record, as header
+struct CsvRecordWriter<W: WriteFinish> {
+    // taken by `finish`, which needs the inner writer back
+    writer: Option<csv::Writer<W>>,
+    headers: Option<Vec<String>>,
+}
+
+impl<W: WriteFinish> CsvRecordWriter<W> {
+    fn new(writer: W, delimiter: u8) -> Self {
+        let writer = WriterBuilder::new()
+            .delimiter(delimiter)
+            .from_writer(writer);
+        Self {
+            writer: Some(writer),
+            headers: None,
+        }
+    }
+
+    fn writer(&mut self) -> Result<&mut csv::Writer<W>> {
+        self.writer
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("CSV writer is already finished"))
+    }
+}
+
+impl<W: WriteFinish> RecordWriter for CsvRecordWriter<W> {
+    fn write_headers(&mut self, headers: &[String]) -> Result<()> {
+        if self.headers.is_none() {
+            self.writer()?.write_record(headers)?;
+            self.headers = Some(headers.to_vec());
+        }
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        let Value::Object(map) = record else {
+            anyhow::bail!("Record is not an object");
+        };
+        if self.headers.is_none() {
+            let headers = map.keys().cloned().collect::<Vec<_>>();
+            self.write_headers(&headers)?;
+        }
+        let headers = self.headers.as_deref().unwrap_or_default();
+        if let Some(key) = map.keys().find(|k| !headers.contains(k)) {
+            anyhow::bail!("Column {:?} is not in the CSV header", key);
+        }
+        let row = headers
+            .iter()
+            .map(|h| map.get(h).map(cell_to_string).unwrap_or_default())
+            .collect::<Vec<_>>();
+        self.writer()?.write_record(row)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        match self.writer.take() {
+            Some(writer) => finish_csv(writer),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Flush a CSV writer and finish the writer below it
+pub fn finish_csv<W: WriteFinish>(writer: csv::Writer<W>) -> Result<()> {
+    let mut inner = writer.into_inner().map_err(|e| e.into_error())?;
+    inner.finish()
+}
+
+/// Pretty printed JSON array, same output as `serde_json::to_string_pretty`
+struct JsonWriter<W> {
+    writer: W,
+    count: usize,
+}
+
+impl<W: WriteFinish> JsonWriter<W> {
+    fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl<W: WriteFinish> RecordWriter for JsonWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        let sep = if self.count == 0 { "[\n  " } else { ",\n  " };
+        let pretty = serde_json::to_string_pretty(record)?;
+        // strings never contain raw newlines, so every line can be indented as is
+        write!(self.writer, "{}{}", sep, pretty.replace('\n', "\n  "))?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let end = if self.count == 0 { "[]" } else { "\n]" };
+        write!(self.writer, "{}", end)?;
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+/// JSON array with one compact record per line
+struct JsonLinesArrayWriter<W> {
+    writer: W,
+    count: usize,
+}
+
+impl<W: WriteFinish> JsonLinesArrayWriter<W> {
+    fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl<W: WriteFinish> RecordWriter for JsonLinesArrayWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        let sep = if self.count == 0 { "[\n" } else { ",\n" };
+        write!(self.writer, "{}", sep)?;
+        serde_json::to_writer(&mut self.writer, record)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let end = if self.count == 0 { "[]\n" } else { "\n]\n" };
+        write!(self.writer, "{}", end)?;
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+/// One JSON object per line
+struct NdjsonWriter<W> {
+    writer: W,
+}
+
+impl<W: WriteFinish> RecordWriter for NdjsonWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        serde_json::to_writer(&mut self.writer, record)?;
+        writeln!(self.writer)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+/// YAML sequence, every record is written as its own `- ` item
+struct YamlWriter<W> {
+    writer: W,
+    count: usize,
+}
+
+impl<W: WriteFinish> YamlWriter<W> {
+    fn new(writer: W) -> Self {
+        Self { writer, count: 0 }
+    }
+}
+
+impl<W: WriteFinish> RecordWriter for YamlWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        serde_yaml::to_writer(&mut self.writer, &[record])?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.count == 0 {
+            writeln!(self.writer, "[]")?;
+        }
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+/// TOML has no top-level array, so records are written as an array of tables under `key`
+struct TomlWriter<W> {
+    writer: W,
+    key: String,
+    count: usize,
+}
+
+impl<W: WriteFinish> TomlWriter<W> {
+    fn new(writer: W, key: &str) -> Self {
+        Self {
+            writer,
+            key: key.to_string(),
+            count: 0,
+        }
+    }
+}
+
+impl<W: WriteFinish> RecordWriter for TomlWriter<W> {
+    fn write_record(&mut self, record: &Value) -> Result<()> {
+        let Value::Object(map) = record else {
+            anyhow::bail!("Record {} is not an object", self.count);
+        };
+        // TOML has no null, missing values are left out of the table
+        let row = map
+            .iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<Map<_, _>>();
+        let mut doc = Map::new();
+        doc.insert(self.key.clone(), Value::Array(vec![Value::Object(row)]));
+        let content =
+            toml::to_string(&doc).map_err(|e| anyhow::anyhow!("Cannot write TOML: {}", e))?;
+        let sep = if self.count == 0 { "" } else { "\n" };
+        write!(self.writer, "{}{}", sep, content)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.count == 0 {
+            writeln!(self.writer, "{} = []", self.key)?;
+        }
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_json_writer_matches_pretty() -> Result<()> {
+        let records = vec![
+            json!({"name": "Buffon", "kit": 77, "tags": ["gk", {"a": null}]}),
+            json!({"name": "Perin"}),
+        ];
+        let opts = CsvWriterOpts::default();
+        let content = serialize_records(&records, OutputFormat::Json, &opts)?;
+        assert_eq!(content, serde_json::to_string_pretty(&records)?);
+        let content = serialize_records(&[], OutputFormat::Json, &opts)?;
+        assert_eq!(content, "[]");
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv_writer_headers_without_records() -> Result<()> {
+        let mut buf = Vec::new();
+        let mut writer = record_writer(&mut buf, OutputFormat::Csv, &Default::default());
+        writer.write_headers(&["name".to_string(), "kit".to_string()])?;
+        writer.finish()?;
+        drop(writer);
+        assert_eq!(String::from_utf8(buf)?, "name,kit\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_ndjson_writer() -> Result<()> {
+        let records = vec![
+            json!({"name": "Buffon", "kit": 77}),
+            json!({"name": "Perin"}),
+        ];
+        let content = serialize_records(&records, OutputFormat::Ndjson, &Default::default())?;
+        assert_eq!(
+            content,
+            "{\"name\":\"Buffon\",\"kit\":77}\n{\"name\":\"Perin\"}\n"
+        );
+        Ok(())
+    }
+}
@@ -1,9 +1,186 @@
 use anyhow::Result;
 use rand::{seq::SliceRandom, Rng};
-const UPPER: &[u8] = b"ABCDEFGHIJKLMNPQRSTUVWXYZ";
-const LOWER: &[u8] = b"abcdefghijkmnpqrstuvwxyz";
-const NUMBER: &[u8] = b"123456789";
-const SYMBOL: &[u8] = b"!@#$%^&*_";
+use std::{collections::HashSet, fs};
+
+const UPPER: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const LOWER: &str = "abcdefghijklmnopqrstuvwxyz";
+const NUMBER: &str = "0123456789";
+const SYMBOL: &str = "!@#$%^&*_";
+/// Characters easily mistaken for one another, left out unless asked for
+pub const AMBIGUOUS: &str = "0Ool";
+/// Embedded wordlist for passphrases. It is not the EFF large wordlist yet: its
+/// words come from the zxcvbn frequency lists and include proper nouns. The EFF
+/// `eff_large_wordlist.txt` has the same format and replaces the file as is
+const WORDLIST: &str = include_str!("../../assets/wordlist.txt");
+
+/// Which characters a password is made of
+#[derive(Debug, Clone)]
+pub struct PasswordPolicy {
+    pub length: u8,
+    pub upper: bool,
+    pub lower: bool,
+    pub number: bool,
+    pub symbol: bool,
+    /// Symbols used instead of the default ones
+    pub symbols: Option<String>,
+    /// Characters used instead of the classes above
+    pub charset: Option<String>,
+    /// Characters never used
+    pub exclude: String,
+    pub exclude_ambiguous: bool,
+    pub min_upper: usize,
+    pub min_lower: usize,
+    pub min_digits: usize,
+    pub min_symbols: usize,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            length: 16,
+            upper: false,
+            lower: true,
+            number: false,
+            symbol: false,
+            symbols: None,
+            charset: None,
+            exclude: String::new(),
+            exclude_ambiguous: true,
+            min_upper: 0,
+            min_lower: 0,
+            min_digits: 0,
+            min_symbols: 0,
+        }
+    }
+}
+
+/// Characters a password is drawn from, after checking the policy
+#[derive(Debug)]
+struct CharPool {
+    all: Vec<char>,
+    /// Characters of a class with the number of them the password must have
+    required: Vec<(Vec<char>, usize)>,
+}
+
+impl PasswordPolicy {
+    /// Entropy in bits, every character is counted as drawn from the whole pool
+    pub fn entropy(&self) -> Result<f64> {
+        let pool = self.pool()?;
+        Ok(self.length as f64 * (pool.all.len() as f64).log2())
+    }
+
+    /// Check the policy can be met and collect the characters to use
+    fn pool(&self) -> Result<CharPool> {
+        if self.length < 4 {
+            return Err(anyhow::anyhow!("Length must be at least 4"));
+        }
+        let excluded =
+            |c: char| self.exclude.contains(c) || (self.exclude_ambiguous && AMBIGUOUS.contains(c));
+        let allowed = |c: &char| !excluded(*c);
+        let symbols = self.symbols.as_deref().unwrap_or(SYMBOL);
+        if let Some(c) = symbols
+            .chars()
+            .find(|c| c.is_alphanumeric() || c.is_whitespace())
+        {
+            return Err(anyhow::anyhow!("{:?} is not a symbol", c));
+        }
+
+        // characters of each class, with whether the class is used
+        let classes = match &self.charset {
+            Some(charset) => {
+                let charset = charset.chars().collect::<Vec<_>>();
+                let of =
+                    |f: fn(&char) -> bool| charset.iter().copied().filter(f).collect::<String>();
+                [
+                    (
+                        "uppercase letters",
+                        of(|c| c.is_uppercase()),
+                        false,
+                        self.min_upper,
+                    ),
+                    (
+                        "lowercase letters",
+                        of(|c| c.is_lowercase()),
+                        false,
+                        self.min_lower,
+                    ),
+                    ("digits", of(|c| c.is_numeric()), false, self.min_digits),
+                    (
+                        "symbols",
+                        of(|c| !c.is_alphanumeric()),
+                        false,
+                        self.min_symbols,
+                    ),
+                ]
+            }
+            None => [
+                (
+                    "uppercase letters",
+                    UPPER.to_string(),
+                    self.upper,
+                    self.min_upper,
+                ),
+                (
+                    "lowercase letters",
+                    LOWER.to_string(),
+                    self.lower,
+                    self.min_lower,
+                ),
+                ("digits", NUMBER.to_string(), self.number, self.min_digits),
+                (
+                    "symbols",
+                    symbols.to_string(),
+                    self.symbol,
+                    self.min_symbols,
+                ),
+            ],
+        };
+
+        let mut all = Vec::new();
+        let mut required = Vec::new();
+        for (name, chars, used, min) in classes {
+            let chars = chars.chars().filter(allowed).collect::<Vec<_>>();
+            if self.charset.is_none() && !used && min > 0 {
+                return Err(anyhow::anyhow!(
+                    "A minimum of {} is set but {} are not enabled",
+                    name,
+                    name
+                ));
+            }
+            if chars.is_empty() && (used || min > 0) {
+                return Err(anyhow::anyhow!("No {} are left after the exclusions", name));
+            }
+            if used && self.charset.is_none() {
+                all.extend(chars.iter().copied());
+            }
+            // every class asked for is in the password at least once
+            let min = if used { min.max(1) } else { min };
+            if min > 0 {
+                required.push((chars, min));
+            }
+        }
+        if let Some(charset) = &self.charset {
+            all = charset.chars().filter(allowed).collect();
+        }
+        all.sort_unstable();
+        all.dedup();
+        if all.len() < 2 {
+            return Err(anyhow::anyhow!(
+                "At least 2 different characters are needed, {} left",
+                all.len()
+            ));
+        }
+        let total = required.iter().map(|(_, min)| min).sum::<usize>();
+        if total > self.length as usize {
+            return Err(anyhow::anyhow!(
+                "The minimum counts add up to {}, more than the length {}",
+                total,
+                self.length
+            ));
+        }
+        Ok(CharPool { all, required })
+    }
+}
 
 pub fn process_gen_pass(
     length: u8,
@@ -12,35 +189,179 @@ pub fn process_gen_pass(
     number: bool,
     symbol: bool,
 ) -> Result<String> {
-    if length < 4 {
-        return Err(anyhow::anyhow!("Length must be greater than 4"));
-    }
+    let policy = PasswordPolicy {
+        length,
+        upper,
+        lower,
+        number,
+        symbol,
+        ..Default::default()
+    };
+    process_gen_password(&policy)
+}
 
-    let mut password = Vec::new();
-    let mut char_set = Vec::new();
+/// Generate a password following the policy, which is checked first
+pub fn process_gen_password(policy: &PasswordPolicy) -> Result<String> {
+    let pool = policy.pool()?;
     let mut rng = rand::thread_rng();
-    if upper {
-        char_set.extend_from_slice(UPPER);
-        password.push(UPPER[rng.gen_range(0..UPPER.len())]);
+    let mut password = Vec::with_capacity(policy.length as usize);
+    for (chars, min) in &pool.required {
+        for _ in 0..*min {
+            password.push(chars[rng.gen_range(0..chars.len())]);
+        }
     }
-    if lower {
-        char_set.extend_from_slice(LOWER);
-        password.push(LOWER[rng.gen_range(0..LOWER.len())]);
+    for _ in 0..(policy.length as usize - password.len()) {
+        password.push(pool.all[rng.gen_range(0..pool.all.len())]);
     }
-    if number {
-        char_set.extend_from_slice(NUMBER);
-        password.push(NUMBER[rng.gen_range(0..NUMBER.len())]);
+
+    password.shuffle(&mut rng);
+
+    Ok(password.into_iter().collect())
+}
+
+/// Generate a diceware passphrase of `words` words picked from the wordlist
+pub fn process_gen_passphrase(
+    words: usize,
+    separator: &str,
+    capitalize: bool,
+    wordlist: &[String],
+) -> Result<String> {
+    if words == 0 {
+        return Err(anyhow::anyhow!("Number of words must be at least 1"));
     }
-    if symbol {
-        char_set.extend_from_slice(SYMBOL);
-        password.push(SYMBOL[rng.gen_range(0..SYMBOL.len())]);
+    let mut rng = rand::thread_rng();
+    let passphrase = (0..words)
+        .map(|_| {
+            let word = wordlist
+                .choose(&mut rng)
+                .map(|w| w.as_str())
+                .unwrap_or_default();
+            if capitalize {
+                capitalize_word(word)
+            } else {
+                word.to_string()
+            }
+        })
+        .collect::<Vec<_>>();
+    Ok(passphrase.join(separator))
+}
+
+/// Entropy in bits of a passphrase of `words` words from a list of `list_len` words
+pub fn passphrase_entropy(words: usize, list_len: usize) -> f64 {
+    words as f64 * (list_len.max(1) as f64).log2()
+}
+
+/// Load the words of a wordlist file, the embedded list if no file is given.
+/// Lines are either a word or dice numbers and a word like the EFF lists, blank
+/// lines and lines starting with `#` are skipped
+pub fn load_wordlist(path: Option<&str>) -> Result<Vec<String>> {
+    let content = match path {
+        Some(path) => fs::read_to_string(path)?,
+        None => WORDLIST.to_string(),
+    };
+    let mut seen = HashSet::new();
+    let words = content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.split_once(char::is_whitespace) {
+            Some((dice, word)) if dice.chars().all(|c| c.is_ascii_digit()) => word.trim(),
+            _ => line,
+        })
+        .filter(|word| seen.insert(word.to_string()))
+        .map(|word| word.to_string())
+        .collect::<Vec<_>>();
+    if words.len() < 2 {
+        return Err(anyhow::anyhow!(
+            "Wordlist {} has fewer than 2 distinct words",
+            path.unwrap_or("(embedded)")
+        ));
     }
+    Ok(words)
+}
 
-    for _ in 0..(length as usize - password.len()) {
-        password.push(char_set[rng.gen_range(0..char_set.len())]);
+fn capitalize_word(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
     }
+}
 
-    password.shuffle(&mut rng);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_policy() -> Result<()> {
+        let policy = PasswordPolicy {
+            length: 12,
+            upper: true,
+            number: true,
+            min_digits: 4,
+            ..Default::default()
+        };
+        let password = process_gen_password(&policy)?;
+        assert_eq!(password.chars().count(), 12);
+        assert!(password.chars().filter(|c| c.is_ascii_digit()).count() >= 4);
+        assert!(!password.contains(|c| AMBIGUOUS.contains(c)));
+
+        let policy = PasswordPolicy {
+            length: 8,
+            charset: Some("ab12".to_string()),
+            exclude: "1".to_string(),
+            ..Default::default()
+        };
+        let password = process_gen_password(&policy)?;
+        assert!(password.chars().all(|c| "ab2".contains(c)));
+        assert!((policy.entropy()? - 8.0 * 3f64.log2()).abs() < 1e-9);
 
-    Ok(String::from_utf8(password)?)
+        let invalid = [
+            PasswordPolicy {
+                min_digits: 1,
+                ..Default::default()
+            },
+            PasswordPolicy {
+                number: true,
+                exclude: "123456789".to_string(),
+                ..Default::default()
+            },
+            PasswordPolicy {
+                length: 4,
+                upper: true,
+                min_lower: 4,
+                ..Default::default()
+            },
+            PasswordPolicy {
+                symbol: true,
+                symbols: Some("a!".to_string()),
+                ..Default::default()
+            },
+        ];
+        for policy in invalid {
+            assert!(process_gen_password(&policy).is_err(), "{:?}", policy);
+        }
+
+        let short = |length| PasswordPolicy {
+            length,
+            ..Default::default()
+        };
+        assert_eq!(process_gen_password(&short(4))?.chars().count(), 4);
+        let err = process_gen_password(&short(3)).unwrap_err();
+        assert_eq!(err.to_string(), "Length must be at least 4");
+        Ok(())
+    }
+
+    #[test]
+    fn test_gen_passphrase() -> Result<()> {
+        let wordlist = load_wordlist(None)?;
+        assert_eq!(wordlist.len(), 7776);
+        assert_eq!(wordlist[0], "abandon");
+        let passphrase = process_gen_passphrase(6, "-", true, &wordlist)?;
+        let words = passphrase.split('-').collect::<Vec<_>>();
+        assert_eq!(words.len(), 6);
+        assert!(words.iter().all(|w| w.starts_with(char::is_uppercase)));
+        assert!((passphrase_entropy(6, wordlist.len()) - 77.55).abs() < 0.01);
+        Ok(())
+    }
 }
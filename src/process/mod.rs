@@ -1,11 +1,51 @@
 mod b64;
 mod csv_convert;
+mod csv_crypt;
+mod csv_dedup;
+mod csv_diff;
+mod csv_encoding;
+mod csv_fake;
+mod csv_filter;
+mod csv_from;
+mod csv_infer;
+mod csv_join;
+mod csv_mask;
+mod csv_nested;
+mod csv_query;
+mod csv_schema;
+mod csv_slice;
+mod csv_sort;
+mod csv_split;
+mod csv_stats;
+mod csv_table;
+mod csv_writer;
 mod gen_pass;
 mod http_serve;
+mod pass_strength;
 mod text;
 
 pub use b64::*;
 pub use csv_convert::*;
+pub use csv_crypt::*;
+pub use csv_dedup::*;
+pub use csv_diff::*;
+pub use csv_encoding::*;
+pub use csv_fake::*;
+pub use csv_filter::*;
+pub use csv_from::*;
+pub use csv_infer::*;
+pub use csv_join::*;
+pub use csv_mask::*;
+pub use csv_nested::*;
+pub use csv_query::*;
+pub use csv_schema::*;
+pub use csv_slice::*;
+pub use csv_sort::*;
+pub use csv_split::*;
+pub use csv_stats::*;
+pub use csv_table::*;
+pub use csv_writer::*;
 pub use gen_pass::*;
 pub use http_serve::*;
+pub use pass_strength::*;
 pub use text::*;
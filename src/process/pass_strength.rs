@@ -0,0 +1,201 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::io::{BufRead, BufReader};
+use zxcvbn::{time_estimates::CrackTimeSeconds, zxcvbn, Entropy};
+
+use crate::get_reader;
+
+/// zxcvbn estimate of how hard a password is to guess
+#[derive(Debug, Clone, Serialize)]
+pub struct PasswordStrength {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// 0 (too guessable) to 4 (very unguessable)
+    pub score: u8,
+    pub guesses: u64,
+    pub guesses_log10: f64,
+    /// Entropy of the generator, unknown for passwords not generated here
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entropy_bits: Option<f64>,
+    pub crack_times: CrackTimeEstimates,
+    /// What makes the password weak, only given for scores below 3
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub suggestions: Vec<String>,
+}
+
+/// Strength of a password read from a file, by line number
+#[derive(Debug, Clone, Serialize)]
+pub struct PasswordCheck {
+    pub line: usize,
+    #[serde(flatten)]
+    pub strength: PasswordStrength,
+}
+
+/// Time to guess the password for the attack scenarios of zxcvbn
+#[derive(Debug, Clone, Serialize)]
+pub struct CrackTimeEstimates {
+    pub online_throttling_100_per_hour: CrackTime,
+    pub online_no_throttling_10_per_second: CrackTime,
+    pub offline_slow_hashing_1e4_per_second: CrackTime,
+    pub offline_fast_hashing_1e10_per_second: CrackTime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CrackTime {
+    pub seconds: f64,
+    /// Rounded for people, e.g. "3 hours" or "centuries"
+    pub display: String,
+}
+
+impl PasswordStrength {
+    pub fn new(entropy: &Entropy) -> Self {
+        let times = entropy.crack_times();
+        let feedback = entropy.feedback();
+        Self {
+            password: None,
+            score: entropy.score().into(),
+            guesses: entropy.guesses(),
+            guesses_log10: entropy.guesses_log10(),
+            entropy_bits: None,
+            crack_times: CrackTimeEstimates {
+                online_throttling_100_per_hour: times.online_throttling_100_per_hour().into(),
+                online_no_throttling_10_per_second: times
+                    .online_no_throttling_10_per_second()
+                    .into(),
+                offline_slow_hashing_1e4_per_second: times
+                    .offline_slow_hashing_1e4_per_second()
+                    .into(),
+                offline_fast_hashing_1e10_per_second: times
+                    .offline_fast_hashing_1e10_per_second()
+                    .into(),
+            },
+            warning: feedback.and_then(|f| f.warning()).map(|w| w.to_string()),
+            suggestions: feedback
+                .map(|f| f.suggestions().iter().map(|s| s.to_string()).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl From<CrackTimeSeconds> for CrackTime {
+    fn from(time: CrackTimeSeconds) -> Self {
+        let seconds = match time {
+            CrackTimeSeconds::Integer(seconds) => seconds as f64,
+            CrackTimeSeconds::Float(seconds) => seconds,
+        };
+        Self {
+            seconds,
+            display: time.to_string(),
+        }
+    }
+}
+
+/// Generate passwords until one reaches `min_score`, at most `max_attempts` times.
+/// `hint` tells how to get stronger passwords when none is strong enough
+pub fn gen_with_min_score(
+    min_score: u8,
+    max_attempts: usize,
+    hint: &str,
+    mut generate: impl FnMut() -> Result<String>,
+) -> Result<(String, Entropy)> {
+    let mut best = 0;
+    for _ in 0..max_attempts {
+        let password = generate()?;
+        let entropy = zxcvbn(&password, &[]);
+        let score = u8::from(entropy.score());
+        if score >= min_score {
+            return Ok((password, entropy));
+        }
+        best = best.max(score);
+    }
+    Err(anyhow::anyhow!(
+        "No password reached a score of {} in {} attempts, the best scored {}. {}",
+        min_score,
+        max_attempts,
+        best,
+        hint
+    ))
+}
+
+/// Rate the passwords of the input, one per line, blank lines are skipped.
+/// `user_inputs` are words an attacker would try first, like user or company names.
+/// The passwords are only kept in the results when `show` is set
+pub fn process_pass_check(
+    input: &str,
+    user_inputs: &[String],
+    show: bool,
+) -> Result<Vec<PasswordCheck>> {
+    let reader = BufReader::new(get_reader(input)?);
+    let user_inputs = user_inputs.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+    let mut checks = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        // the io error never holds the content of the line
+        let password = line.map_err(|e| anyhow::anyhow!("Cannot read line {}: {}", i + 1, e))?;
+        if password.is_empty() {
+            continue;
+        }
+        let mut strength = PasswordStrength::new(&zxcvbn(&password, &user_inputs));
+        if show {
+            strength.password = Some(password);
+        }
+        checks.push(PasswordCheck {
+            line: i + 1,
+            strength,
+        });
+    }
+    Ok(checks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_min_score() -> Result<()> {
+        let (password, entropy) = gen_with_min_score(4, 10, "", || Ok("x7$Kq!2vLp@9Rz".into()))?;
+        assert_eq!(password, "x7$Kq!2vLp@9Rz");
+        let strength = PasswordStrength::new(&entropy);
+        assert_eq!(strength.score, 4);
+        assert!(
+            strength
+                .crack_times
+                .offline_fast_hashing_1e10_per_second
+                .seconds
+                > 0.0
+        );
+
+        let hint = "Use more words";
+        let err = gen_with_min_score(3, 5, hint, || Ok("password".into())).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No password reached a score of 3 in 5 attempts, the best scored 0. Use more words"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_pass_check() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("passwords.txt");
+        std::fs::write(&path, "password\n\nacme2024juventus\nx7$Kq!2vLp@9Rz\n")?;
+        let path = path.to_str().unwrap();
+        let checks = process_pass_check(path, &[], false)?;
+        let lines = checks.iter().map(|c| c.line).collect::<Vec<_>>();
+        assert_eq!(lines, [1, 3, 4]);
+        assert_eq!(checks[0].strength.score, 0);
+        assert!(checks[0].strength.warning.is_some());
+        assert!(checks.iter().all(|c| c.strength.password.is_none()));
+        assert_eq!(checks[2].strength.score, 4);
+
+        // user inputs make a password built on them weaker
+        let hinted = process_pass_check(path, &["acme".into(), "juventus".into()], true)?;
+        assert!(hinted[1].strength.guesses < checks[1].strength.guesses);
+        assert_eq!(
+            hinted[1].strength.password.as_deref(),
+            Some("acme2024juventus")
+        );
+        Ok(())
+    }
+}
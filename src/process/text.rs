@@ -5,7 +5,7 @@ use base64::prelude::*;
 use base64::Engine;
 use chacha20poly1305::aead::generic_array::GenericArray;
 use chacha20poly1305::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
     ChaCha20Poly1305,
 };
 use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey};
@@ -132,7 +132,7 @@ impl KeyLoader for Ed25519Verifier {
 }
 
 #[derive(Debug)]
-struct ChaCha20Poly1305Encryptor {
+pub struct ChaCha20Poly1305Encryptor {
     key: [u8; 32],
 }
 
@@ -151,25 +151,14 @@ impl KeyLoader for ChaCha20Poly1305Encryptor {
 }
 impl TextEncryptor for ChaCha20Poly1305Encryptor {
     fn encrypt(&self, reader: &mut dyn Read) -> Result<Vec<u8>> {
-        let key = self.key.into();
-        let cipher = ChaCha20Poly1305::new(&key);
-        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
         let mut buff = Vec::new();
         reader.read_to_end(&mut buff)?;
-        let encrypted = cipher
-            .encrypt(&nonce, &buff[..])
-            .map_err(|_| anyhow::anyhow!("Encrypt error"))?;
-
-        let mut result = Vec::new();
-        result.extend_from_slice(&nonce);
-        println!("result.len(): {:?}", result.len());
-        result.extend_from_slice(&encrypted);
-        Ok(result)
+        self.encrypt_with_aad(&buff, &[])
     }
 }
 
 #[derive(Debug)]
-struct ChaCha20Poly1305Decryptor {
+pub struct ChaCha20Poly1305Decryptor {
     key: [u8; 32],
 }
 
@@ -182,16 +171,10 @@ impl KeyLoader for ChaCha20Poly1305Decryptor {
 
 impl TextDecryptor for ChaCha20Poly1305Decryptor {
     fn decrypt(&self, reader: &mut dyn Read) -> Result<Vec<u8>> {
-        let key = self.key.into();
-        let cipher = ChaCha20Poly1305::new(&key);
         let mut buff = Vec::new();
         reader.read_to_end(&mut buff)?;
         let decoded_buff = BASE64_URL_SAFE_NO_PAD.decode(&buff)?;
-        let nonce = GenericArray::from_slice(&decoded_buff[..12]);
-        let decrypted = cipher
-            .decrypt(nonce, &decoded_buff[12..])
-            .map_err(|_| anyhow::anyhow!("Decrypt error"))?;
-        Ok(decrypted)
+        self.decrypt_with_aad(&decoded_buff, &[])
     }
 }
 
@@ -269,6 +252,11 @@ impl Blake3 {
     fn new(key: [u8; 32]) -> Self {
         Self { key }
     }
+
+    /// Hasher keyed with this key, e.g. to read more than 32 bytes with `finalize_xof`
+    pub fn keyed_hasher(&self) -> blake3::Hasher {
+        blake3::Hasher::new_keyed(&self.key)
+    }
 }
 
 impl TryFrom<&[u8]> for Blake3 {
@@ -317,6 +305,22 @@ impl ChaCha20Poly1305Encryptor {
     fn new(key: [u8; 32]) -> Self {
         Self { key }
     }
+
+    /// Encrypt with associated data, which is authenticated but not stored: the same
+    /// data must be given to decrypt. Returns the nonce followed by the ciphertext
+    pub fn encrypt_with_aad(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let key = self.key.into();
+        let cipher = ChaCha20Poly1305::new(&key);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let encrypted = cipher
+            .encrypt(&nonce, Payload { msg: data, aad })
+            .map_err(|_| anyhow::anyhow!("Encrypt error"))?;
+
+        let mut result = Vec::new();
+        result.extend_from_slice(&nonce);
+        result.extend_from_slice(&encrypted);
+        Ok(result)
+    }
 }
 
 impl TryFrom<&[u8]> for ChaCha20Poly1305Encryptor {
@@ -335,6 +339,26 @@ impl ChaCha20Poly1305Decryptor {
     fn new(key: [u8; 32]) -> Self {
         Self { key }
     }
+
+    /// Decrypt the nonce and ciphertext of `encrypt_with_aad` with the same associated data
+    pub fn decrypt_with_aad(&self, data: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let key = self.key.into();
+        let cipher = ChaCha20Poly1305::new(&key);
+        if data.len() < 12 {
+            anyhow::bail!("Decrypt error: content too short");
+        }
+        let nonce = GenericArray::from_slice(&data[..12]);
+        let decrypted = cipher
+            .decrypt(
+                nonce,
+                Payload {
+                    msg: &data[12..],
+                    aad,
+                },
+            )
+            .map_err(|_| anyhow::anyhow!("Decrypt error"))?;
+        Ok(decrypted)
+    }
 }
 
 impl TryFrom<&[u8]> for ChaCha20Poly1305Decryptor {
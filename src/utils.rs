@@ -1,6 +1,64 @@
 use anyhow::Result;
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
 use std::fs;
-use std::io::Read;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression of a data file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Detect the compression from the file extension, e.g. `data.csv.gz`
+    pub fn from_path(path: &str) -> Self {
+        match split_compression(path)
+            .1
+            .map(|ext| ext.to_lowercase())
+            .as_deref()
+        {
+            Some("gz" | "gzip") => Compression::Gzip,
+            Some("zst" | "zstd") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Detect the compression of a file from its magic bytes, or from its extension
+    pub fn detect(path: &str) -> Result<Self> {
+        let mut magic = Vec::with_capacity(ZSTD_MAGIC.len());
+        fs::File::open(path)?
+            .take(ZSTD_MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+        Ok(Self::from_magic(&magic).unwrap_or_else(|| Self::from_path(path)))
+    }
+
+    fn from_magic(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Split the compression extension off a path: `data.csv.gz` is (`data.csv`, Some("gz"))
+pub fn split_compression(path: &str) -> (&str, Option<&str>) {
+    match path.rsplit_once('.') {
+        Some((stem, ext))
+            if matches!(ext.to_lowercase().as_str(), "gz" | "gzip" | "zst" | "zstd") =>
+        {
+            (stem, Some(ext))
+        }
+        _ => (path, None),
+    }
+}
 
 pub fn get_reader(input: &str) -> Result<Box<dyn Read>> {
     Ok(if input == "-" {
@@ -9,3 +67,171 @@ pub fn get_reader(input: &str) -> Result<Box<dyn Read>> {
         Box::new(fs::File::open(input)?)
     })
 }
+
+pub fn get_writer(output: &str) -> Result<Box<dyn Write>> {
+    Ok(if output == "-" {
+        Box::new(std::io::stdout())
+    } else {
+        Box::new(fs::File::create(output)?)
+    })
+}
+
+/// Open a data file, gzip and zstd input is decompressed. The compression is
+/// detected from the magic bytes, or from the extension
+pub fn get_data_reader(input: &str) -> Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(get_reader(input)?);
+    let compression = Compression::from_magic(reader.fill_buf()?)
+        .unwrap_or_else(|| Compression::from_path(input));
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+    })
+}
+
+/// Writer of a data file. Compressed streams end with a footer that is only
+/// written by `finish`, so a file is complete once `finish` returns
+pub enum DataWriter {
+    Plain(Box<dyn Write>),
+    Gzip(GzEncoder<Box<dyn Write>>),
+    Zstd(zstd::Encoder<'static, Box<dyn Write>>),
+}
+
+impl DataWriter {
+    /// Write the end of the compressed stream and flush the file
+    pub fn finish(&mut self) -> Result<()> {
+        match self {
+            DataWriter::Plain(writer) => writer.flush()?,
+            DataWriter::Gzip(encoder) => {
+                encoder.try_finish()?;
+                encoder.get_mut().flush()?;
+            }
+            DataWriter::Zstd(encoder) => {
+                encoder.do_finish()?;
+                encoder.get_mut().flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for DataWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            DataWriter::Plain(writer) => writer.write(buf),
+            DataWriter::Gzip(encoder) => encoder.write(buf),
+            DataWriter::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            DataWriter::Plain(writer) => writer.flush(),
+            DataWriter::Gzip(encoder) => encoder.flush(),
+            DataWriter::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// A writer with an end to write once all the data is written, see [`DataWriter`]
+pub trait WriteFinish: Write {
+    fn finish(&mut self) -> Result<()> {
+        self.flush()?;
+        Ok(())
+    }
+}
+
+impl WriteFinish for Vec<u8> {}
+
+impl WriteFinish for DataWriter {
+    fn finish(&mut self) -> Result<()> {
+        DataWriter::finish(self)
+    }
+}
+
+impl<W: WriteFinish> WriteFinish for BufWriter<W> {
+    fn finish(&mut self) -> Result<()> {
+        self.flush()?;
+        self.get_mut().finish()
+    }
+}
+
+impl<W: WriteFinish + ?Sized> WriteFinish for &mut W {
+    fn finish(&mut self) -> Result<()> {
+        (**self).finish()
+    }
+}
+
+/// Create a data file, compressed when its extension is `.gz` or `.zst`.
+/// Call `finish` after the last write
+pub fn get_data_writer(output: &str) -> Result<DataWriter> {
+    data_writer(output, get_writer(output)?)
+}
+
+/// Open a data file to write more at its end. A compressed file gets a new
+/// stream, which is read back as part of the same file
+pub fn append_data_writer(output: &str) -> Result<DataWriter> {
+    let file = fs::OpenOptions::new().append(true).open(output)?;
+    data_writer(output, Box::new(file))
+}
+
+fn data_writer(output: &str, writer: Box<dyn Write>) -> Result<DataWriter> {
+    Ok(match Compression::from_path(output) {
+        Compression::None => DataWriter::Plain(writer),
+        Compression::Gzip => {
+            DataWriter::Gzip(GzEncoder::new(writer, flate2::Compression::default()))
+        }
+        Compression::Zstd => DataWriter::Zstd(zstd::Encoder::new(writer, 0)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compressed_round_trip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in ["data.csv.gz", "data.csv.zst"] {
+            let path = dir.path().join(name);
+            let path = path.to_str().unwrap();
+            let mut writer = get_data_writer(path)?;
+            writer.write_all(b"a,b\n1,2\n")?;
+            writer.finish()?;
+            drop(writer);
+
+            let mut content = String::new();
+            get_data_reader(path)?.read_to_string(&mut content)?;
+            assert_eq!(content, "a,b\n1,2\n");
+            // detected from the magic bytes without the extension
+            let plain = dir.path().join("data");
+            fs::copy(path, &plain)?;
+            let mut content = String::new();
+            get_data_reader(plain.to_str().unwrap())?.read_to_string(&mut content)?;
+            assert_eq!(content, "a,b\n1,2\n");
+        }
+        assert_eq!(split_compression("out.json.GZ"), ("out.json", Some("GZ")));
+
+        // the end of the stream is written by finish, so a full disk is reported there
+        struct Full(usize);
+        impl Write for Full {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                if self.0 < buf.len() {
+                    return Err(std::io::Error::other("disk full"));
+                }
+                self.0 -= buf.len();
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        // room for the gzip header only
+        let full = Box::new(Full(10));
+        let mut writer = DataWriter::Gzip(GzEncoder::new(full, flate2::Compression::default()));
+        writer.write_all(b"a,b\n")?;
+        assert!(writer.finish().is_err());
+        assert_eq!(split_compression("out.json"), ("out.json", None));
+        Ok(())
+    }
+}